@@ -1,7 +1,30 @@
 BEGIN;
 
+-- vault_id is random and bound to every encrypted blob, along with the
+-- blob's table and row id, as associated data.
 CREATE TABLE Metadata (
-    challenge BLOB NOT NULL
+    challenge BLOB NOT NULL,
+    vault_id BLOB NOT NULL,
+    -- password slots also need the key file, as Argon2id secret input
+    requires_key_file INTEGER NOT NULL DEFAULT 0,
+    -- AEAD of the data blobs: 0 AES-256-GCM, 1 XChaCha20-Poly1305
+    cipher INTEGER NOT NULL DEFAULT 0
+);
+
+-- Each slot holds the vault data key, encrypted with a key derived from one
+-- unlocking secret (password, key file) with Argon2id. wrapped_key_bound is
+-- 1 if the wrapped key is bound to the vault id and slot id as associated
+-- data, 0 for keys wrapped before schema version 8.
+CREATE TABLE KeySlot (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    kind INTEGER NOT NULL,
+    label TEXT NOT NULL,
+    kdf_salt BLOB NOT NULL,
+    kdf_memory INTEGER NOT NULL,
+    kdf_iterations INTEGER NOT NULL,
+    kdf_parallelism INTEGER NOT NULL,
+    wrapped_key BLOB NOT NULL,
+    wrapped_key_bound INTEGER NOT NULL DEFAULT 0
 );
 
 CREATE TABLE EntryGroup (
@@ -22,5 +45,37 @@ CREATE TABLE EntryGroupParent (
     PRIMARY KEY(parent_id, child_id)
 );
 
+-- Single row holding the encrypted group membership of entries and parents
+-- of groups, for vaults hiding their structure. Entry.group_id is then always
+-- the root group and EntryGroupParent is empty.
+CREATE TABLE StructureIndex (
+    id INTEGER PRIMARY KEY,
+    data BLOB NOT NULL
+);
+
+-- Named password generator policies
+CREATE TABLE PasswordPolicy (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    data BLOB NOT NULL
+);
+
+-- Files attached to entries: the name and size are in data, the content in
+-- chunks encrypted separately, so that large files are streamed. Each chunk
+-- starts with its attachment id and position before encryption.
+CREATE TABLE Attachment (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    entry_id INTEGER NOT NULL REFERENCES Entry(id),
+    data BLOB NOT NULL
+);
+
+CREATE TABLE AttachmentChunk (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    attachment_id INTEGER NOT NULL REFERENCES Attachment(id),
+    position INTEGER NOT NULL,
+    data BLOB NOT NULL,
+
+    UNIQUE(attachment_id, position)
+);
+
 COMMIT;
 
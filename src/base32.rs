@@ -0,0 +1,63 @@
+//! RFC 4648 base32, without padding, used for recovery keys and OTP secrets
+
+const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Encode without padding. The last character holds the remaining bits when
+/// `bytes.len()` is not a multiple of 5.
+pub(crate) fn encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len() * 8 / 5 + 1);
+    let mut buffer = 0u64;
+    let mut bits = 0;
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u64;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(ALPHABET[(buffer >> bits) as usize & 31] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(ALPHABET[(buffer << (5 - bits)) as usize & 31] as char);
+    }
+    return encoded;
+}
+
+/// Decode uppercase base32 without padding, `None` on any other character.
+/// Trailing bits not filling a byte are dropped.
+pub(crate) fn decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len() * 5 / 8);
+    let mut buffer = 0u64;
+    let mut bits = 0;
+    for ch in encoded.bytes() {
+        let value = ALPHABET.iter().position(|&c| c == ch)?;
+        buffer = (buffer << 5) | value as u64;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    return Some(decoded);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, encode};
+
+    #[test]
+    fn rfc_4648_vectors() {
+        let vectors = [
+            ("f", "MY"),
+            ("fo", "MZXQ"),
+            ("foo", "MZXW6"),
+            ("foob", "MZXW6YQ"),
+            ("fooba", "MZXW6YTB"),
+            ("foobar", "MZXW6YTBOI"),
+        ];
+        for (decoded, encoded) in vectors {
+            assert_eq!(encode(decoded.as_bytes()), encoded);
+            assert_eq!(decode(encoded).unwrap(), decoded.as_bytes());
+        }
+        assert_eq!(decode("MZXW6YTB="), None);
+    }
+}
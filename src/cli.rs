@@ -1,18 +1,28 @@
 use std::{
     env,
-    io::{self, Write},
-    num::IntErrorKind,
-    path::PathBuf,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
 };
 
-use clap::{CommandFactory, Parser, Subcommand};
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
 use clap_complete::Shell;
+use zeroize::Zeroizing;
 
 use crate::{
-    encryption::Cipher,
-    model::{database::Database, Credentials, EntryData, EntryGroupData},
+    encryption::{CipherAlgorithm, CipherKey, KdfParams, KeyFileHash},
+    generator::{PassphrasePolicy, PasswordPolicy},
+    model::{
+        database::Database, path::ItemRef, Credentials, CustomField, EntryData, EntryGroupData,
+        KeySlotKind,
+    },
+    otp::{Otp, OtpKind},
+    recovery::{self, RecoveryKey, RecoveryShare},
+    secret::SecretString,
+    timestamp, Error, Result,
 };
 
+const DEFAULT_VAULT_SUFFIX: &str = ".sqlite";
+
 /// Command-line password manager
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -24,17 +34,120 @@ pub struct CliArgs {
     #[arg(short, long)]
     credentials_file: Option<PathBuf>,
 
+    /// vault file, by default derived from the username in the user data
+    /// directory, or in DB_DIR with DB_SUFFIX if set
+    #[arg(long, env = "PASSWORD_CLI_VAULT")]
+    vault: Option<PathBuf>,
+
     /// do not hide password inputs on stdin (not recommended if running from a
     /// terminal)
     #[arg(long)]
     show_password: bool,
+
+    /// unlock the database with a key file registered in one of its key
+    /// slots instead of the password
+    #[arg(long)]
+    unlock_key_file: Option<PathBuf>,
+
+    /// key file required on top of the password, see `keyfile generate`;
+    /// vaults created with it cannot be opened by password alone
+    #[arg(long, env = "PASSWORD_CLI_KEY_FILE")]
+    key_file: Option<PathBuf>,
+
+    /// record when secrets of entries are printed, which writes to the vault
+    /// on reads
+    #[arg(long, env = "PASSWORD_CLI_RECORD_ACCESS")]
+    record_access: bool,
 }
 
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// create a new encrypted database
     #[command(visible_alias = "c")]
-    Create,
+    Create {
+        /// Argon2id memory cost, in KiB
+        #[arg(long, default_value_t = KdfParams::DEFAULT_MEMORY_KIB)]
+        kdf_memory: u32,
+
+        /// Argon2id number of iterations
+        #[arg(long, default_value_t = KdfParams::DEFAULT_ITERATIONS)]
+        kdf_iterations: u32,
+
+        /// Argon2id degree of parallelism
+        #[arg(long, default_value_t = KdfParams::DEFAULT_PARALLELISM)]
+        kdf_parallelism: u32,
+
+        /// name of the root group
+        #[arg(long, default_value_t = Database::DEFAULT_ROOT_GROUP_NAME.to_string())]
+        root_name: String,
+
+        /// generate the master passphrase instead of reading a password,
+        /// also offered when an empty password is typed
+        #[arg(long)]
+        generate_passphrase: bool,
+
+        /// encrypt which group each entry and group belongs to, so that only
+        /// the number of entries and groups is visible
+        #[arg(long)]
+        hide_structure: bool,
+
+        /// AEAD encrypting the data: aes256gcm or xchacha20
+        #[arg(long, default_value_t = CipherAlgorithm::default())]
+        cipher: CipherAlgorithm,
+
+        /// do not generate a recovery key
+        #[arg(long)]
+        no_recovery_key: bool,
+
+        /// also write the recovery key to this new emergency kit file, as
+        /// HTML if it ends with .html, as text otherwise
+        #[arg(long, value_name = "PATH", conflicts_with = "no_recovery_key")]
+        emergency_kit: Option<PathBuf>,
+
+        #[command(flatten)]
+        passphrase: PassphraseArgs,
+    },
+
+    /// change the password of the key slot used to open the database
+    Passwd,
+
+    /// re-encrypt all data with another cipher, keeping the data key
+    Reencrypt {
+        /// new AEAD: aes256gcm or xchacha20
+        #[arg(long)]
+        cipher: CipherAlgorithm,
+    },
+
+    /// rotate the data key, re-encrypting all data
+    Rekey {
+        /// remove the key slots other than the one used to open the
+        /// database, which cannot be rewrapped without their secrets
+        #[arg(long)]
+        remove_other_slots: bool,
+    },
+
+    /// set a new master password with the recovery key, when the password
+    /// is lost
+    Recover,
+
+    /// split the vault recovery between several people, or recover with
+    /// their shares
+    Recovery {
+        #[command(subcommand)]
+        command: RecoveryCommand,
+    },
+
+    /// manage the key slots unlocking the database
+    Slot {
+        #[command(subcommand)]
+        command: SlotCommand,
+    },
+
+    /// show whether the group tree is hidden, or hide or show it
+    Structure {
+        #[command(subcommand)]
+        command: Option<StructureCommand>,
+    },
 
     /// retrieve entry or group data
     #[command(visible_alias = "g")]
@@ -53,6 +166,55 @@ enum Commands {
         command: AddCommand,
     },
 
+    /// edit an entry or a group
+    Edit {
+        #[command(subcommand)]
+        command: EditCommand,
+    },
+
+    /// remove an entry or a group
+    Rm {
+        #[command(subcommand)]
+        command: RmCommand,
+    },
+
+    /// move an entry or a group to another group
+    Mv {
+        #[command(subcommand)]
+        command: MvCommand,
+    },
+
+    /// list the tags of the entries, with the number of entries having each
+    Tags,
+
+    /// manage the files attached to entries
+    Attach {
+        #[command(subcommand)]
+        command: AttachCommand,
+    },
+
+    /// generate a random password, or a passphrase
+    #[command(args_conflicts_with_subcommands = true)]
+    Generate {
+        #[command(subcommand)]
+        command: Option<GenerateCommand>,
+
+        #[command(flatten)]
+        generator: GeneratorArgs,
+    },
+
+    /// manage the saved password generator policies
+    Policy {
+        #[command(subcommand)]
+        command: PolicyCommand,
+    },
+
+    /// manage key files used as a second factor with `--key-file`
+    Keyfile {
+        #[command(subcommand)]
+        command: KeyfileCommand,
+    },
+
     /// generate miscellaneous files, print them to stdout
     Gen {
         /// generate zsh completion script
@@ -61,19 +223,371 @@ enum Commands {
     },
 }
 
+#[derive(Subcommand, Debug)]
+enum RecoveryCommand {
+    /// print new recovery shares, any `threshold` of which unlock the vault,
+    /// revoking the previous shares
+    Split {
+        /// number of shares needed to recover
+        #[arg(short, long)]
+        threshold: u8,
+
+        /// number of shares to print
+        #[arg(short, long)]
+        shares: u8,
+    },
+
+    /// set a new master password with a threshold of recovery shares
+    Combine,
+}
+
+#[derive(Subcommand, Debug)]
+enum KeyfileCommand {
+    /// write a new random key file, readable only by its owner
+    Generate {
+        /// file to create, it must not exist
+        path: PathBuf,
+    },
+}
+
 #[derive(Subcommand, Debug)]
 enum GenTarget {
     /// print zsh completion to stdout
     Zsh { cmd_name: PathBuf },
 }
 
+#[derive(Subcommand, Debug)]
+enum GenerateCommand {
+    /// generate a diceware-style passphrase from the bundled word list
+    Passphrase {
+        #[command(flatten)]
+        passphrase: PassphraseArgs,
+    },
+}
+
+#[derive(Args, Debug)]
+struct PassphraseArgs {
+    /// number of words
+    #[arg(short, long, default_value_t = PassphrasePolicy::DEFAULT_WORDS)]
+    words: usize,
+
+    /// separator between words
+    #[arg(short, long, default_value_t = PassphrasePolicy::DEFAULT_SEPARATOR.to_string())]
+    separator: String,
+
+    /// capitalize each word
+    #[arg(long)]
+    capitalize: bool,
+
+    /// append a random digit to the last word
+    #[arg(long)]
+    digit: bool,
+}
+
+impl PassphraseArgs {
+    fn passphrase_policy(&self) -> PassphrasePolicy {
+        return PassphrasePolicy::new(
+            self.words,
+            self.separator.clone(),
+            self.capitalize,
+            self.digit,
+        );
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum PolicyCommand {
+    /// list the saved policies
+    #[command(visible_alias = "ls")]
+    List,
+
+    /// save a policy, replacing the policy with the same name
+    Save {
+        name: String,
+
+        #[command(flatten)]
+        generator: GeneratorArgs,
+    },
+
+    /// remove a saved policy
+    #[command(visible_alias = "rm")]
+    Remove { name: String },
+}
+
+/// Entry fields beyond the name, username and password
+#[derive(Args, Debug)]
+struct EntryFieldArgs {
+    /// add a URL, can be repeated
+    #[arg(long, value_name = "URL")]
+    url: Vec<String>,
+
+    /// set the notes, replacing the current ones
+    #[arg(long)]
+    notes: Option<String>,
+
+    /// set a plain custom field, can be repeated
+    #[arg(long, value_name = "NAME=VALUE")]
+    field: Vec<String>,
+
+    /// set a concealed custom field (e.g. a PIN), its value is read from
+    /// stdin, can be repeated
+    #[arg(long, value_name = "NAME")]
+    concealed_field: Vec<String>,
+
+    /// set the TOTP or HOTP secret, its otpauth:// URI is read from stdin
+    #[arg(long)]
+    otp: bool,
+
+    /// add a tag, can be repeated
+    #[arg(long, value_name = "TAG")]
+    tag: Vec<String>,
+
+    /// mark the entry as a favorite
+    #[arg(long)]
+    favorite: bool,
+}
+
+impl EntryFieldArgs {
+    /// Field names which `get entries --field` reads from the entry itself
+    const BUILTIN_FIELDS: [&'static str; 12] = [
+        "name",
+        "username",
+        "password",
+        "url",
+        "notes",
+        "otp",
+        "tags",
+        "favorite",
+        "created",
+        "modified",
+        "password-changed",
+        "accessed",
+    ];
+
+    fn is_empty(&self) -> bool {
+        return self.url.is_empty()
+            && self.notes.is_none()
+            && self.field.is_empty()
+            && self.concealed_field.is_empty()
+            && !self.otp
+            && self.tag.is_empty()
+            && !self.favorite;
+    }
+
+    /// Set the fields on `entry_data`, prompting for concealed values
+    fn apply(self, entry_data: &mut EntryData, show_password: bool) -> Result<()> {
+        for url in self.url {
+            entry_data.add_url(url.into());
+        }
+        if let Some(notes) = self.notes {
+            entry_data.set_notes(notes.into());
+        }
+        for field in self.field {
+            let Some((name, value)) = field.split_once('=') else {
+                return Err(Error::InvalidInput(format!(
+                    "invalid field \"{}\", expected NAME=VALUE",
+                    field
+                )));
+            };
+            let name = Self::custom_field_name(name)?;
+            entry_data.set_custom_field(CustomField::new(name.into(), value.into(), false));
+        }
+        for name in self.concealed_field {
+            let name = Self::custom_field_name(&name)?;
+            print!("- {}: ", name);
+            io::stdout().flush()?;
+            let value = read_password(show_password)?;
+            entry_data.set_custom_field(CustomField::new(name.into(), value, true));
+        }
+        if self.otp {
+            print!("- otpauth URI: ");
+            io::stdout().flush()?;
+            let uri = read_password(show_password)?;
+            entry_data.set_otp(Some(Otp::parse_uri(&uri)?));
+        }
+        for tag in self.tag {
+            entry_data.add_tag(Self::tag(&tag)?.into());
+        }
+        if self.favorite {
+            entry_data.set_favorite(true);
+        }
+
+        return Ok(());
+    }
+
+    /// Tags are listed separated by commas, so they cannot contain any
+    fn tag(tag: &str) -> Result<&str> {
+        let tag = tag.trim();
+        if tag.is_empty() || tag.contains(',') {
+            return Err(Error::InvalidInput(format!("invalid tag \"{}\"", tag)));
+        }
+        return Ok(tag);
+    }
+
+    fn custom_field_name(name: &str) -> Result<&str> {
+        let name = name.trim();
+        if name.is_empty() || name.contains('=') || Self::BUILTIN_FIELDS.contains(&name) {
+            return Err(Error::InvalidInput(format!(
+                "invalid custom field name \"{}\"",
+                name
+            )));
+        }
+        return Ok(name);
+    }
+}
+
+/// Password generator options, overriding those of the policy if one is given
+#[derive(Args, Debug)]
+struct GeneratorArgs {
+    /// start from the saved policy with this name
+    #[arg(long)]
+    policy: Option<String>,
+
+    /// password length
+    #[arg(short, long)]
+    length: Option<usize>,
+
+    /// do not use lowercase letters
+    #[arg(long)]
+    no_lowercase: bool,
+
+    /// do not use uppercase letters
+    #[arg(long)]
+    no_uppercase: bool,
+
+    /// do not use digits
+    #[arg(long)]
+    no_digits: bool,
+
+    /// do not use symbols
+    #[arg(long)]
+    no_symbols: bool,
+
+    /// symbols to pick from instead of the default ones
+    #[arg(long)]
+    symbols: Option<String>,
+
+    /// do not use look-alike characters such as 0, O, 1, l and I
+    #[arg(long)]
+    exclude_ambiguous: bool,
+
+    /// characters that must not appear in the password
+    #[arg(long)]
+    exclude: Option<String>,
+}
+
+impl GeneratorArgs {
+    /// Whether any option is given, they only apply with `--generate` on
+    /// `add entry` and `edit entry`
+    fn is_given(&self) -> bool {
+        return self.policy.is_some()
+            || self.length.is_some()
+            || self.no_lowercase
+            || self.no_uppercase
+            || self.no_digits
+            || self.no_symbols
+            || self.symbols.is_some()
+            || self.exclude_ambiguous
+            || self.exclude.is_some();
+    }
+
+    /// Refuse options that would be ignored without `--generate`
+    fn check_generate(&self, generate: bool) -> Result<()> {
+        if !generate && self.is_given() {
+            return Err(Error::InvalidInput(
+                "password generator options require --generate".into(),
+            ));
+        }
+        return Ok(());
+    }
+
+    /// Apply the options to the saved policy, or to the default policy
+    fn password_policy(&self, db: Option<&Database>) -> Result<PasswordPolicy> {
+        let mut policy = match (&self.policy, db) {
+            (Some(name), Some(db)) => db.policy(name)?,
+            _ => PasswordPolicy::default(),
+        };
+
+        if let Some(length) = self.length {
+            policy.set_length(length);
+        }
+        if self.no_lowercase {
+            policy.set_lowercase(false);
+        }
+        if self.no_uppercase {
+            policy.set_uppercase(false);
+        }
+        if self.no_digits {
+            policy.set_digits(false);
+        }
+        if let Some(symbols) = &self.symbols {
+            policy.set_symbols(symbols.clone());
+        }
+        if self.no_symbols {
+            policy.set_symbols(String::new());
+        }
+        if self.exclude_ambiguous {
+            policy.set_exclude_ambiguous(true);
+        }
+        if let Some(exclude) = &self.exclude {
+            policy.set_excluded(exclude.clone());
+        }
+
+        return Ok(policy);
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum SlotCommand {
+    /// list the key slots
+    #[command(visible_alias = "ls")]
+    List,
+
+    /// add a key slot unlocked by another password
+    AddPassword {
+        /// description of the slot, e.g. its owner
+        #[arg(short, long)]
+        label: String,
+    },
+
+    /// add a key slot unlocked by the content of a key file
+    AddKeyFile {
+        /// existing file whose content unlocks the slot
+        path: PathBuf,
+
+        /// description of the slot
+        #[arg(short, long)]
+        label: Option<String>,
+    },
+
+    /// generate a new recovery key, revoking the previous one
+    AddRecoveryKey {
+        /// also write the recovery key to this new emergency kit file
+        #[arg(long, value_name = "PATH")]
+        emergency_kit: Option<PathBuf>,
+    },
+
+    /// revoke a key slot
+    #[command(visible_alias = "rm")]
+    Remove { id: i64 },
+}
+
+#[derive(Subcommand, Debug)]
+enum StructureCommand {
+    /// move the group tree into the encrypted structure index
+    Hide,
+
+    /// store the group tree in clear again
+    Show,
+}
+
 #[derive(Subcommand, Debug)]
 enum GetCommand {
     #[command(visible_alias = "e")]
     Entries {
-        /// filters the results to those with the given parent group id
+        /// filters the results to those in the given parent group (id or path)
         #[arg(short = 'g', long)]
-        parent_group: Option<i64>,
+        parent_group: Option<ItemRef>,
 
         /// filters the results to those matching the given name
         #[arg(short, long)]
@@ -91,230 +605,1236 @@ enum GetCommand {
         #[arg(short, long)]
         id: bool,
 
-        /// get all values associated with each entry (name, username, password, etc.)
+        /// get all values associated with each entry (name, username, password,
+        /// timestamps, etc.)
         #[arg(short, long)]
         all: bool,
+
+        /// get the given field from each entry: url, notes, otp, tags,
+        /// favorite, created, modified, password-changed, accessed or the name
+        /// of a custom field, can be repeated
+        #[arg(short, long, value_name = "NAME")]
+        field: Vec<String>,
+
+        /// filters the results to those last modified before the given date:
+        /// YYYY-MM-DD, YYYY-MM-DDTHH:MM:SSZ or a number of days ago such as 90d
+        #[arg(long, value_name = "DATE", value_parser = parse_date)]
+        modified_before: Option<u64>,
+
+        /// filters the results to those last modified after the given date
+        #[arg(long, value_name = "DATE", value_parser = parse_date)]
+        modified_after: Option<u64>,
+
+        /// filters the results to those whose password was last changed
+        /// before the given date
+        #[arg(long, value_name = "DATE", value_parser = parse_date)]
+        password_changed_before: Option<u64>,
+
+        /// sort the results, oldest first for timestamps
+        #[arg(long, value_enum)]
+        sort: Option<EntrySort>,
+
+        /// filters the results to those with the given tag, can be repeated
+        /// to require several tags
+        #[arg(short, long, value_name = "TAG")]
+        tag: Vec<String>,
+
+        /// filters the results to favorite entries
+        #[arg(long)]
+        favorite: bool,
+    },
+
+    /// print the current one-time password of an entry, followed by the
+    /// seconds it remains valid for TOTP; for HOTP the counter is incremented
+    Otp {
+        /// id or path of the entry, prompted for if missing
+        entry: Option<ItemRef>,
     },
 
     #[command(visible_alias = "g")]
     Groups {
-        /// filters the results to those with the given parent group id
+        /// filters the results to those in the given parent group (id or path)
         #[arg(short = 'g', long)]
-        parent_group: Option<i64>,
+        parent_group: Option<ItemRef>,
     },
 }
 
+/// Order of `get entries` results, by id by default
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum EntrySort {
+    Name,
+    Created,
+    Modified,
+    PasswordChanged,
+    Accessed,
+}
+
 #[derive(Subcommand, Debug)]
-enum AddCommand {
-    /// add an entry
+enum EditCommand {
+    /// edit an entry, prompting for each field unless one is given as an
+    /// option
     #[command(visible_alias = "e")]
-    Entry {},
-
-    /// add a group
-    #[command(visible_alias = "g")]
-    Group {},
-}
+    Entry {
+        /// id or path of the entry, prompted for if missing
+        entry: Option<ItemRef>,
 
-pub fn run(args: CliArgs) -> Result<(), ()> {
-    let show_password = args.show_password;
+        /// new entry name
+        #[arg(short, long)]
+        name: Option<String>,
 
-    let username: Box<str>;
-    let password: Box<str>;
+        /// new entry username
+        #[arg(short, long)]
+        username: Option<String>,
 
-    if let Some(path) = args.credentials_file {
-        let file_content = std::fs::read_to_string(path).expect("could not read credentials file");
-        let mut file_lines = file_content.lines();
-        username = file_lines.next().expect("missing username").into();
-        password = file_lines.next().expect("missing password").into();
-    } else {
-        let mut stderr = io::stderr();
-        stderr.write_all(b"Username: ").unwrap();
-        stderr.flush().unwrap();
+        /// read a new entry password from stdin
+        #[arg(short, long)]
+        password: bool,
 
-        match std::io::stdin().lines().next() {
-            Some(line) => username = line.expect("TODO: why can this error ?").into(),
-            None => return Err(()), // EOF
-        }
+        /// replace the entry password with a generated one
+        #[arg(long, conflicts_with = "password")]
+        generate: bool,
 
-        stderr.write_all(b"Password: ").unwrap();
-        stderr.flush().unwrap();
-        password = read_password(show_password).into();
-    }
+        #[command(flatten)]
+        generator: Box<GeneratorArgs>,
 
-    let credentials = Credentials::new(username, password);
-    let cipher = Cipher::new(&(&credentials).into());
+        #[command(flatten)]
+        fields: Box<EntryFieldArgs>,
 
-    // TODO: use clap "env" feature
-    let db_dir = env::var("DB_DIR").expect("missing env var DB_DIR");
-    let db_suffix = env::var("DB_SUFFIX").expect("missing env var DB_SUFFIX");
-    let db_path = format!("{}{}{}", db_dir, credentials.user(), db_suffix);
+        /// remove a URL, can be repeated
+        #[arg(long, value_name = "URL")]
+        remove_url: Vec<String>,
 
-    match args.command {
-        Commands::Create => {
-            Database::create(db_path, &cipher).expect("could not create database");
+        /// remove a custom field, can be repeated
+        #[arg(long, value_name = "NAME")]
+        remove_field: Vec<String>,
 
-            return Ok(());
-        }
+        /// remove the TOTP or HOTP secret
+        #[arg(long, conflicts_with = "otp")]
+        remove_otp: bool,
 
-        Commands::Get { command, separator } => match command {
-            GetCommand::Entries {
-                parent_group,
-                name,
-                username,
-                password,
-                id,
-                all,
-            } => {
-                let db =
-                    Database::open(db_path, &cipher).expect("could not open database connection");
-                let matched = db.entries(name.as_deref(), parent_group).unwrap();
-                let id = id || all;
-                let password = password || all;
-                let username = username || all;
+        /// remove a tag, can be repeated
+        #[arg(long, value_name = "TAG")]
+        remove_tag: Vec<String>,
 
-                for entry in matched {
-                    if id {
-                        print!("{}{}", entry.id(), separator);
-                    }
+        /// no longer mark the entry as a favorite
+        #[arg(long, conflicts_with = "favorite")]
+        no_favorite: bool,
+    },
 
-                    let entry_data = entry.data();
-                    print!("{}", entry_data.name());
+    /// rename a group, prompting for the name unless it is given as an option
+    #[command(visible_alias = "g")]
+    Group {
+        /// id or path of the group, prompted for if missing
+        group: Option<ItemRef>,
 
-                    if username {
-                        print!("{}{}", separator, entry_data.username());
-                    }
-                    if password {
-                        print!("{}{}", separator, entry_data.password());
-                    }
-                    println!();
-                }
+        /// new group name
+        #[arg(short, long)]
+        name: Option<String>,
+    },
+}
 
-                return Ok(());
-            }
+#[derive(Subcommand, Debug)]
+enum RmCommand {
+    /// remove an entry
+    #[command(visible_alias = "e")]
+    Entry {
+        /// id or path of the entry, prompted for if missing
+        entry: Option<ItemRef>,
 
-            GetCommand::Groups { parent_group } => {
-                let db =
-                    Database::open(db_path, &cipher).expect("could not open database connection");
-                let matched = db.groups(parent_group).unwrap();
+        /// do not ask for confirmation
+        #[arg(short, long)]
+        force: bool,
+    },
 
-                for group in matched {
-                    println!("{}{}{}", group.id(), separator, group.data().name());
-                }
+    /// remove a group, which must be empty unless --recursive is given
+    #[command(visible_alias = "g")]
+    Group {
+        /// id or path of the group, prompted for if missing
+        group: Option<ItemRef>,
 
-                return Ok(());
-            }
-        },
+        /// also remove the group's descendant groups and all their entries
+        #[arg(short, long)]
+        recursive: bool,
+
+        /// do not ask for confirmation
+        #[arg(short, long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum AttachCommand {
+    /// list the attachments of an entry, with their sizes in bytes
+    #[command(visible_alias = "ls")]
+    List {
+        /// id or path of the entry
+        entry: ItemRef,
+    },
+
+    /// attach a file to an entry
+    Add {
+        /// id or path of the entry
+        entry: ItemRef,
+
+        /// file to attach, `-` for stdin
+        file: PathBuf,
+
+        /// name of the attachment, by default the file name
+        #[arg(short, long)]
+        name: Option<String>,
+    },
+
+    /// write an attachment to stdout, or to a new file
+    Get {
+        /// id or path of the entry
+        entry: ItemRef,
+
+        /// name of the attachment
+        name: String,
+
+        /// write the attachment to this new file instead of stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+
+    /// remove an attachment
+    #[command(visible_alias = "rm")]
+    Remove {
+        /// id or path of the entry
+        entry: ItemRef,
+
+        /// name of the attachment
+        name: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum MvCommand {
+    /// move an entry to another group
+    #[command(visible_alias = "e")]
+    Entry {
+        /// id or path of the entry, prompted for if missing
+        entry: Option<ItemRef>,
+
+        /// id or path of the destination group, prompted for if missing
+        #[arg(short = 'g', long)]
+        group: Option<ItemRef>,
+    },
+
+    /// move a group under another group
+    #[command(visible_alias = "g")]
+    Group {
+        /// id or path of the group, prompted for if missing
+        group: Option<ItemRef>,
+
+        /// id or path of the new parent group, prompted for if missing
+        #[arg(short = 'g', long)]
+        parent_group: Option<ItemRef>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum AddCommand {
+    /// add an entry
+    #[command(visible_alias = "e")]
+    Entry {
+        /// generate the entry password instead of prompting for it
+        #[arg(long)]
+        generate: bool,
+
+        #[command(flatten)]
+        generator: GeneratorArgs,
+
+        #[command(flatten)]
+        fields: Box<EntryFieldArgs>,
+    },
+
+    /// add a group
+    #[command(visible_alias = "g")]
+    Group {},
+}
+
+pub fn run(args: CliArgs) -> Result<()> {
+    // commands needing neither credentials nor a vault
+    match &args.command {
+        Commands::Keyfile {
+            command: KeyfileCommand::Generate { path },
+        } => return write_key_file(path),
+
+        // a vault is only read for a saved policy
+        Commands::Generate {
+            command: None,
+            generator,
+        } if generator.policy.is_none() => {
+            let policy = generator.password_policy(None)?;
+            println!("{}", generate_password(&policy)?.expose());
+
+            return Ok(());
+        }
+
+        Commands::Generate {
+            command: Some(GenerateCommand::Passphrase { passphrase }),
+            ..
+        } => {
+            let policy = passphrase.passphrase_policy();
+            let passphrase = policy.generate()?;
+            eprintln!("estimated entropy: {:.0} bits", policy.entropy_bits());
+            println!("{}", passphrase.expose());
+
+            return Ok(());
+        }
+
+        _ => {}
+    }
+
+    let show_password = args.show_password;
+    let unlock_key_file = args.unlock_key_file;
+    let record_access = args.record_access;
+    let generate_passphrase = matches!(
+        args.command,
+        Commands::Create {
+            generate_passphrase: true,
+            ..
+        }
+    );
+    let recover = matches!(
+        args.command,
+        Commands::Recover
+            | Commands::Recovery {
+                command: RecoveryCommand::Combine
+            }
+    );
+    let needs_password = unlock_key_file.is_none() && !generate_passphrase && !recover;
+
+    let username: Box<str>;
+    let password: SecretString;
+
+    if let Some(path) = args.credentials_file {
+        let file_content = Zeroizing::new(std::fs::read_to_string(path)?);
+        let mut file_lines = file_content.lines();
+        username = match file_lines.next() {
+            Some(line) => line.into(),
+            None => return Err(Error::InvalidInput("missing username".into())),
+        };
+        password = match file_lines.next() {
+            Some(line) => line.into(),
+            None if !needs_password => "".into(),
+            None => return Err(Error::InvalidInput("missing password".into())),
+        };
+    } else {
+        let mut stderr = io::stderr();
+        stderr.write_all(b"Username: ")?;
+        stderr.flush()?;
+
+        match std::io::stdin().lines().next() {
+            Some(line) => username = line?.into(),
+            None => return Err(Error::InvalidInput("missing username".into())), // EOF
+        }
+
+        if needs_password {
+            stderr.write_all(b"Password: ")?;
+            stderr.flush()?;
+            password = read_password(show_password)?;
+        } else {
+            password = "".into();
+        }
+    }
+
+    let mut credentials = Credentials::new(username, password);
+    if let Some(key_file_path) = &args.key_file {
+        let key_file = Zeroizing::new(std::fs::read(key_file_path)?);
+        credentials = credentials.with_key_file(key_file.as_slice());
+    }
+
+    let db_path = vault_path(args.vault, credentials.user())?;
+
+    match args.command {
+        Commands::Create {
+            kdf_memory,
+            kdf_iterations,
+            kdf_parallelism,
+            root_name,
+            generate_passphrase,
+            hide_structure,
+            cipher,
+            no_recovery_key,
+            emergency_kit,
+            passphrase,
+        } => {
+            if !is_valid_name(&root_name) {
+                return Err(Error::InvalidInput(format!(
+                    "invalid group name \"{}\"",
+                    root_name
+                )));
+            }
+            let generate_passphrase = generate_passphrase
+                || (credentials.password().is_empty()
+                    && io::stdin().is_terminal()
+                    && confirm("Empty password, generate a master passphrase instead? [y/N] ")?);
+            if !generate_passphrase && credentials.password().is_empty() {
+                return Err(Error::InvalidInput(
+                    "empty master password, use --generate-passphrase to generate one".into(),
+                ));
+            }
+            let credentials = if generate_passphrase {
+                let policy = passphrase.passphrase_policy();
+                let master_passphrase = policy.generate()?;
+                eprintln!(
+                    "generated master passphrase ({:.0} bits), write it down:",
+                    policy.entropy_bits()
+                );
+                println!("{}", master_passphrase.expose());
+                Credentials::new(credentials.user().into(), master_passphrase)
+            } else {
+                credentials
+            };
+
+            if let Some(parent) = db_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            // fail before creating the vault
+            let kit_file = emergency_kit
+                .as_deref()
+                .map(create_private_file)
+                .transpose()?;
+            let remove_emergency_kit = || {
+                if let Some(path) = &emergency_kit {
+                    let _ = std::fs::remove_file(path);
+                }
+            };
+
+            let kdf = KdfParams::with_random_salt(kdf_memory, kdf_iterations, kdf_parallelism);
+            let mut db =
+                Database::create(&db_path, &credentials, kdf, &root_name).map_err(|err| {
+                    remove_emergency_kit();
+                    return err;
+                })?;
+            let set_up = |db: &mut Database| -> Result<()> {
+                db.set_structure_hidden(hide_structure)?;
+                db.set_cipher_algorithm(cipher)?;
+
+                if !no_recovery_key {
+                    let kdf =
+                        KdfParams::with_random_salt(kdf_memory, kdf_iterations, kdf_parallelism);
+                    issue_recovery_key(
+                        db,
+                        &db_path,
+                        credentials.user(),
+                        &kdf,
+                        emergency_kit.as_deref().zip(kit_file),
+                    )?;
+                }
+
+                return Ok(());
+            };
+            if let Err(err) = set_up(&mut db) {
+                // a vault without its recovery key must not be left behind
+                drop(db);
+                let _ = std::fs::remove_file(&db_path);
+                remove_emergency_kit();
+                return Err(err);
+            }
+
+            return Ok(());
+        }
+
+        Commands::Passwd => {
+            let mut db = open_database(db_path, &credentials, unlock_key_file.as_deref())?;
+
+            eprint!("New password: ");
+            let new_password = read_password(show_password)?;
+            eprint!("Confirm new password: ");
+            if read_password(show_password)? != new_password {
+                return Err(Error::InvalidInput("passwords do not match".into()));
+            }
+
+            let new_credentials = Credentials::new(credentials.user().into(), new_password);
+            let new_kdf = match db.kdf() {
+                Some(kdf) => KdfParams::with_random_salt(
+                    kdf.memory_kib(),
+                    kdf.iterations(),
+                    kdf.parallelism(),
+                ),
+                None => KdfParams::recommended(),
+            };
+            db.change_password(&new_credentials, new_kdf)?;
+
+            return Ok(());
+        }
+
+        Commands::Reencrypt { cipher } => {
+            let mut db = open_database(db_path, &credentials, unlock_key_file.as_deref())?;
+
+            db.set_cipher_algorithm(cipher)?;
+            eprintln!("the vault data is encrypted with {}", cipher);
+
+            return Ok(());
+        }
+
+        Commands::Rekey { remove_other_slots } => {
+            let mut db = open_database(db_path, &credentials, unlock_key_file.as_deref())?;
+
+            let removed = db.rekey(CipherKey::generate(), remove_other_slots)?;
+            eprintln!("rotated the data key");
+            for slot in removed {
+                let command = match slot.kind() {
+                    KeySlotKind::Password => "slot add-password",
+                    KeySlotKind::KeyFile => "slot add-key-file",
+                    KeySlotKind::Recovery => "slot add-recovery-key",
+                    KeySlotKind::RecoveryShares => "recovery split",
+                };
+                eprintln!(
+                    "removed key slot {} ({}, \"{}\"), issue it again with {}",
+                    slot.id(),
+                    slot.kind().name(),
+                    slot.label(),
+                    command
+                );
+            }
+
+            return Ok(());
+        }
+
+        Commands::Recover => {
+            eprint!("Recovery key: ");
+            let recovery_key = RecoveryKey::parse(&read_password(show_password)?)?;
+            let mut db = Database::open_with_recovery_key(&db_path, &recovery_key)?;
+            reset_master_password(&mut db, &credentials, show_password)?;
+            eprintln!("master password reset, the recovery key remains valid");
+
+            return Ok(());
+        }
+
+        Commands::Recovery { command } => match command {
+            RecoveryCommand::Split { threshold, shares } => {
+                let db = open_database(&db_path, &credentials, unlock_key_file.as_deref())?;
+
+                let shares =
+                    db.add_recovery_shares(&KdfParams::recommended(), threshold, shares)?;
+                eprintln!(
+                    "recovery shares, give one to each person, any {} of them unlock the vault:",
+                    threshold
+                );
+                for share in shares {
+                    println!("{}\t{}", share.number(), share.to_printable().expose());
+                }
+
+                return Ok(());
+            }
+
+            RecoveryCommand::Combine => {
+                let mut shares: Vec<RecoveryShare> = Vec::new();
+                loop {
+                    eprint!("Recovery share: ");
+                    let share = RecoveryShare::parse(&read_password(show_password)?)?;
+                    let threshold = share.threshold() as usize;
+                    shares.push(share);
+                    if shares.len() >= threshold {
+                        break;
+                    }
+                    eprintln!("{} of {} shares", shares.len(), threshold);
+                }
+
+                let mut db = Database::open_with_recovery_shares(&db_path, shares)?;
+                reset_master_password(&mut db, &credentials, show_password)?;
+                eprintln!("master password reset, the recovery shares remain valid");
+
+                return Ok(());
+            }
+        },
+
+        Commands::Slot { command } => {
+            let db = open_database(&db_path, &credentials, unlock_key_file.as_deref())?;
+
+            match command {
+                SlotCommand::List => {
+                    for slot in db.key_slots()? {
+                        println!("{}\t{}\t{}", slot.id(), slot.kind().name(), slot.label());
+                    }
+                }
+
+                SlotCommand::AddPassword { label } => {
+                    eprint!("Slot password: ");
+                    let slot_password = read_password(show_password)?;
+                    eprint!("Confirm slot password: ");
+                    if read_password(show_password)? != slot_password {
+                        return Err(Error::InvalidInput("passwords do not match".into()));
+                    }
+
+                    let slot_id = db.add_key_slot(
+                        KeySlotKind::Password,
+                        label.as_str(),
+                        slot_password.as_bytes(),
+                        &KdfParams::recommended(),
+                    )?;
+                    println!("{}", slot_id);
+                }
+
+                SlotCommand::AddKeyFile { path, label } => {
+                    let key_file = Zeroizing::new(std::fs::read(&path)?);
+                    let label = label.unwrap_or_else(|| path.display().to_string());
+                    let slot_id = db.add_key_slot(
+                        KeySlotKind::KeyFile,
+                        label.as_str(),
+                        key_file.as_slice(),
+                        &KdfParams::recommended(),
+                    )?;
+                    println!("{}", slot_id);
+                }
+
+                SlotCommand::AddRecoveryKey { emergency_kit } => {
+                    // fail before revoking the previous recovery key
+                    let kit_file = emergency_kit
+                        .as_deref()
+                        .map(create_private_file)
+                        .transpose()?;
+                    issue_recovery_key(
+                        &db,
+                        &db_path,
+                        credentials.user(),
+                        &KdfParams::recommended(),
+                        emergency_kit.as_deref().zip(kit_file),
+                    )?;
+                }
+
+                SlotCommand::Remove { id } => {
+                    if db.unlocked_slot_id() == Some(id) {
+                        eprintln!("warning: removing the key slot used to open the database");
+                    }
+                    db.remove_key_slot(id)?;
+                }
+            }
+
+            return Ok(());
+        }
+
+        Commands::Structure { command } => {
+            let mut db = open_database(db_path, &credentials, unlock_key_file.as_deref())?;
 
-        Commands::Add { command } => {
             match command {
-                AddCommand::Entry {} => {
-                    let db = Database::open(db_path, &cipher)
-                        .expect("could not open database connection");
-                    let stdin = std::io::stdin();
-                    let mut stdout = std::io::stdout();
-                    let mut parent_group = String::new();
-                    let mut entry_name = String::new();
-                    let mut username = String::new();
+                None => {
+                    let state = if db.hides_structure()? {
+                        "hidden"
+                    } else {
+                        "clear"
+                    };
+                    println!("{}", state);
+                }
+                Some(StructureCommand::Hide) => db.set_structure_hidden(true)?,
+                Some(StructureCommand::Show) => db.set_structure_hidden(false)?,
+            }
+
+            return Ok(());
+        }
+
+        Commands::Get { command, separator } => match command {
+            GetCommand::Entries {
+                parent_group,
+                name,
+                username,
+                password,
+                id,
+                all,
+                field,
+                tag,
+                favorite,
+                modified_before,
+                modified_after,
+                password_changed_before,
+                sort,
+            } => {
+                let db = open_database(db_path, &credentials, unlock_key_file.as_deref())?;
+                let parent_group = match parent_group {
+                    Some(parent_group) => Some(db.resolve_group(&parent_group)?),
+                    None => None,
+                };
+                let mut matched = db.entries(name.as_deref(), parent_group)?;
+                matched.retain(|entry| {
+                    let entry_data = entry.data();
+                    // entries from before timestamps count as older than any date
+                    let before = |timestamp: Option<u64>, date: Option<u64>| match (timestamp, date)
+                    {
+                        (Some(timestamp), Some(date)) => timestamp < date,
+                        _ => true,
+                    };
+                    let modified_after = match modified_after {
+                        Some(date) => entry_data
+                            .modified()
+                            .is_some_and(|modified| modified > date),
+                        None => true,
+                    };
+                    return tag.iter().all(|tag| entry_data.has_tag(tag))
+                        && (!favorite || entry_data.is_favorite())
+                        && before(entry_data.modified(), modified_before)
+                        && modified_after
+                        && before(entry_data.password_changed(), password_changed_before);
+                });
+                match sort {
+                    Some(EntrySort::Name) => {
+                        matched.sort_by(|a, b| a.data().name().cmp(b.data().name()))
+                    }
+                    Some(EntrySort::Created) => matched.sort_by_key(|entry| entry.data().created()),
+                    Some(EntrySort::Modified) => {
+                        matched.sort_by_key(|entry| entry.data().modified())
+                    }
+                    Some(EntrySort::PasswordChanged) => {
+                        matched.sort_by_key(|entry| entry.data().password_changed())
+                    }
+                    Some(EntrySort::Accessed) => {
+                        matched.sort_by_key(|entry| entry.data().accessed())
+                    }
+                    None => {}
+                }
+                let id = id || all;
+                let password = password || all;
+                let username = username || all;
+                let format_timestamp =
+                    |timestamp: Option<u64>| timestamp.map(timestamp::format).unwrap_or_default();
+                let mut accessed = Vec::new();
+
+                for entry in matched {
+                    if id {
+                        print!("{}{}", entry.id(), separator);
+                    }
+
+                    let entry_data = entry.data();
+                    print!("{}", entry_data.name());
+
+                    if username {
+                        print!("{}{}", separator, entry_data.username());
+                    }
+                    if password {
+                        print!("{}{}", separator, entry_data.password());
+                    }
+                    if all {
+                        for timestamp in [
+                            entry_data.created(),
+                            entry_data.modified(),
+                            entry_data.password_changed(),
+                            entry_data.accessed(),
+                        ] {
+                            print!("{}{}", separator, format_timestamp(timestamp));
+                        }
+                    }
+                    let mut reveals_secret = password;
+                    for field in &field {
+                        let value = match field.as_str() {
+                            "name" => entry_data.name().to_string(),
+                            "username" => entry_data.username().to_string(),
+                            "password" => entry_data.password().to_string(),
+                            "url" => entry_data.urls().join(","),
+                            "notes" => entry_data.notes().to_string(),
+                            "otp" => entry_data
+                                .otp()
+                                .map(|otp| otp.to_uri().expose().to_string())
+                                .unwrap_or_default(),
+                            "tags" => entry_data.tags().collect::<Vec<_>>().join(","),
+                            "favorite" => entry_data.is_favorite().to_string(),
+                            "created" => format_timestamp(entry_data.created()),
+                            "modified" => format_timestamp(entry_data.modified()),
+                            "password-changed" => format_timestamp(entry_data.password_changed()),
+                            "accessed" => format_timestamp(entry_data.accessed()),
+                            name => entry_data
+                                .custom_field(name)
+                                .map(|field| field.value().to_string())
+                                .unwrap_or_default(),
+                        };
+                        reveals_secret |= match field.as_str() {
+                            "password" => true,
+                            "notes" => !entry_data.notes().is_empty(),
+                            "otp" => entry_data.otp().is_some(),
+                            name => entry_data
+                                .custom_field(name)
+                                .is_some_and(|field| field.is_concealed()),
+                        };
+                        print!("{}{}", separator, value);
+                    }
+                    println!();
+                    if reveals_secret {
+                        accessed.push(entry.id());
+                    }
+                }
+                if record_access {
+                    record_entry_access(&db, &accessed);
+                }
+
+                return Ok(());
+            }
+
+            GetCommand::Otp { entry } => {
+                let db = open_database(db_path, &credentials, unlock_key_file.as_deref())?;
+                let entry_id = match entry {
+                    Some(entry) => db.resolve_entry(&entry)?,
+                    None => db.resolve_entry(&prompt_item("- entry: ")?)?,
+                };
+                let entry = db.entry(entry_id)?;
+                let Some(otp) = entry.data().otp() else {
+                    return Err(Error::InvalidInput(format!(
+                        "the entry \"{}\" has no OTP secret",
+                        entry.data().name()
+                    )));
+                };
+
+                match otp.kind() {
+                    OtpKind::Totp { .. } => {
+                        let (code, remaining) = otp.totp_code(timestamp::now())?;
+                        println!("{}{}{}", code.expose(), separator, remaining);
+                    }
+                    OtpKind::Hotp { .. } => {
+                        let code = db.next_hotp_code(entry_id)?;
+                        println!("{}", code.expose());
+                    }
+                }
+                if record_access {
+                    record_entry_access(&db, &[entry_id]);
+                }
+
+                return Ok(());
+            }
+
+            GetCommand::Groups { parent_group } => {
+                let db = open_database(db_path, &credentials, unlock_key_file.as_deref())?;
+                let parent_group = match parent_group {
+                    Some(parent_group) => Some(db.resolve_group(&parent_group)?),
+                    None => None,
+                };
+                let matched = db.groups(parent_group)?;
+
+                for group in matched {
+                    println!("{}{}{}", group.id(), separator, group.data().name());
+                }
+
+                return Ok(());
+            }
+        },
+
+        Commands::Add { command } => {
+            match command {
+                AddCommand::Entry {
+                    generate,
+                    generator,
+                    fields,
+                } => {
+                    generator.check_generate(generate)?;
+                    let db = open_database(db_path, &credentials, unlock_key_file.as_deref())?;
 
                     println!("Adding entry");
-                    print!("- parent group id (leave empty for root): ");
-                    stdout.flush().ok();
-                    // TODO: handle number of bytes read (EOF)
-                    let _bytes_read = stdin.read_line(&mut parent_group).unwrap();
-                    let parent_group: i64 = parent_group
-                        .trim()
-                        .parse::<i64>()
-                        .or_else(|err| {
-                            if let IntErrorKind::Empty = err.kind() {
-                                Ok(db.root_group_id())
-                            } else {
-                                Err(err)
-                            }
-                        })
-                        .unwrap();
+                    let parent_group =
+                        prompt_group_id(&db, "- parent group (leave empty for root): ")?;
 
-                    print!("- entry name: ");
-                    stdout.flush().ok();
-                    stdin.read_line(&mut entry_name).unwrap();
-                    let entry_name = entry_name.trim();
+                    let entry_name = prompt_line("- entry name: ")?;
                     // validate entry name
-                    if !entry_name
-                        .chars()
-                        .all(|ch| ch == ' ' || ch.is_ascii_graphic())
-                    {
-                        todo!("handle invalid input \"{}\"", entry_name);
+                    if !is_valid_name(&entry_name) {
+                        return Err(Error::InvalidInput(format!(
+                            "invalid entry name \"{}\"",
+                            entry_name
+                        )));
                     }
 
-                    print!("- entry username: ");
-                    stdout.flush().ok();
-                    stdin.read_line(&mut username).unwrap();
-                    let username = username.trim();
+                    let username = prompt_line("- entry username: ")?;
                     // TODO: validate
 
-                    print!("- entry password: ");
-                    stdout.flush().ok();
-                    let password = read_password(show_password);
-                    let password = password.trim();
+                    let password = if generate {
+                        generate_password(&generator.password_policy(Some(&db))?)?
+                    } else {
+                        print!("- entry password: ");
+                        io::stdout().flush()?;
+                        SecretString::from(read_password(show_password)?.trim())
+                    };
                     // TODO: validate
 
-                    let entry_data =
-                        EntryData::new(entry_name.into(), username.into(), password.into());
-                    db.insert_entry(parent_group, &entry_data).unwrap();
+                    let mut entry_data =
+                        EntryData::new(entry_name.into(), username.into(), password);
+                    fields.apply(&mut entry_data, show_password)?;
+                    db.insert_entry(parent_group, &entry_data)?;
 
                     return Ok(());
                 }
 
                 AddCommand::Group {} => {
-                    let mut db = Database::open(db_path, &cipher)
-                        .expect("could not open database connection");
-                    let stdin = std::io::stdin();
-                    let mut stdout = std::io::stdout();
-                    let mut parent_group = String::new();
-                    let mut group_name = String::new();
+                    let mut db = open_database(db_path, &credentials, unlock_key_file.as_deref())?;
 
                     println!("Adding group");
-                    print!("- parent group id (leave empty for root): ");
-                    stdout.flush().ok();
-                    // TODO: handle number of bytes read (EOF)
-                    let _bytes_read = stdin.read_line(&mut parent_group).unwrap();
-                    let parent_group_id: i64 = parent_group
-                        .trim()
-                        .parse::<i64>()
-                        .or_else(|err| {
-                            if let IntErrorKind::Empty = err.kind() {
-                                Ok(db.root_group_id())
-                            } else {
-                                Err(err)
-                            }
-                        })
-                        .unwrap();
+                    let parent_group_id =
+                        prompt_group_id(&db, "- parent group (leave empty for root): ")?;
 
-                    print!("- group name: ");
-                    stdout.flush().ok();
-                    stdin.read_line(&mut group_name).unwrap();
-                    let group_name = group_name.trim();
+                    let group_name = prompt_line("- group name: ")?;
                     // validate group name
-                    if !group_name
-                        .chars()
-                        .all(|ch| ch == ' ' || ch.is_ascii_graphic())
-                    {
-                        todo!("handle invalid input \"{}\"", group_name);
+                    if !is_valid_name(&group_name) {
+                        return Err(Error::InvalidInput(format!(
+                            "invalid group name \"{}\"",
+                            group_name
+                        )));
                     }
 
                     let group_data = EntryGroupData::new(group_name.into());
-                    db.insert_entry_group(parent_group_id, &group_data).unwrap();
+                    db.insert_entry_group(parent_group_id, &group_data)?;
+
+                    return Ok(());
+                }
+            }
+        }
+
+        Commands::Edit { command } => match command {
+            EditCommand::Entry {
+                entry,
+                name,
+                username,
+                password,
+                generate,
+                generator,
+                fields,
+                remove_url,
+                remove_field,
+                remove_otp,
+                remove_tag,
+                no_favorite,
+            } => {
+                // generator options would otherwise count as no option given
+                // and start an interactive edit
+                generator.check_generate(generate)?;
+                let db = open_database(db_path, &credentials, unlock_key_file.as_deref())?;
+                let entry_id = match entry {
+                    Some(entry) => db.resolve_entry(&entry)?,
+                    None => db.resolve_entry(&prompt_item("- entry: ")?)?,
+                };
+                let entry = db.entry(entry_id)?;
+                let mut entry_data = entry.data().clone();
+                let interactive = name.is_none()
+                    && username.is_none()
+                    && !password
+                    && !generate
+                    && fields.is_empty()
+                    && remove_url.is_empty()
+                    && remove_field.is_empty()
+                    && !remove_otp
+                    && remove_tag.is_empty()
+                    && !no_favorite;
+
+                let name = if interactive {
+                    println!("Editing entry (leave empty to keep the current value)");
+                    Some(prompt_line(&format!(
+                        "- entry name [{}]: ",
+                        entry_data.name()
+                    ))?)
+                    .filter(|name| !name.is_empty())
+                } else {
+                    name
+                };
+                if let Some(name) = name {
+                    if !is_valid_name(&name) {
+                        return Err(Error::InvalidInput(format!(
+                            "invalid entry name \"{}\"",
+                            name
+                        )));
+                    }
+                    entry_data.set_name(name.into());
+                }
+
+                let username = if interactive {
+                    Some(prompt_line(&format!(
+                        "- entry username [{}]: ",
+                        entry_data.username()
+                    ))?)
+                    .filter(|username| !username.is_empty())
+                } else {
+                    username
+                };
+                if let Some(username) = username {
+                    entry_data.set_username(username.into());
+                }
+
+                if generate {
+                    let new_password = generate_password(&generator.password_policy(Some(&db))?)?;
+                    entry_data.set_password(new_password);
+                } else if interactive || password {
+                    print!("- entry password: ");
+                    io::stdout().flush()?;
+                    let new_password = read_password(show_password)?;
+                    let new_password = new_password.trim();
+                    if !new_password.is_empty() {
+                        entry_data.set_password(new_password.into());
+                    }
+                }
+
+                for url in remove_url {
+                    if !entry_data.remove_url(&url) {
+                        return Err(Error::InvalidInput(format!("no URL \"{}\"", url)));
+                    }
+                }
+                for name in remove_field {
+                    if !entry_data.remove_custom_field(&name) {
+                        return Err(Error::InvalidInput(format!("no field \"{}\"", name)));
+                    }
+                }
+                if remove_otp {
+                    if entry_data.otp().is_none() {
+                        return Err(Error::InvalidInput("no OTP secret".into()));
+                    }
+                    entry_data.set_otp(None);
+                }
+                for tag in remove_tag {
+                    if !entry_data.remove_tag(&tag) {
+                        return Err(Error::InvalidInput(format!("no tag \"{}\"", tag)));
+                    }
+                }
+                if no_favorite {
+                    entry_data.set_favorite(false);
+                }
+                fields.apply(&mut entry_data, show_password)?;
+
+                db.update_entry(entry_id, &entry_data)?;
 
+                return Ok(());
+            }
+
+            EditCommand::Group { group, name } => {
+                let db = open_database(db_path, &credentials, unlock_key_file.as_deref())?;
+                let group_id = match group {
+                    Some(group) => db.resolve_group(&group)?,
+                    None => db.resolve_group(&prompt_item("- group: ")?)?,
+                };
+                let group = db.entry_group(group_id)?;
+                let mut group_data = group.data().clone();
+
+                let name = match name {
+                    Some(name) => name,
+                    None => prompt_line(&format!("- group name [{}]: ", group_data.name()))?,
+                };
+                if name.is_empty() {
+                    return Ok(());
+                }
+                if !is_valid_name(&name) {
+                    return Err(Error::InvalidInput(format!(
+                        "invalid group name \"{}\"",
+                        name
+                    )));
+                }
+                group_data.set_name(name.into());
+
+                db.update_entry_group(group_id, &group_data)?;
+
+                return Ok(());
+            }
+        },
+
+        Commands::Rm { command } => match command {
+            RmCommand::Entry { entry, force } => {
+                let db = open_database(db_path, &credentials, unlock_key_file.as_deref())?;
+                let entry_id = match entry {
+                    Some(entry) => db.resolve_entry(&entry)?,
+                    None => db.resolve_entry(&prompt_item("- entry: ")?)?,
+                };
+                let entry = db.entry(entry_id)?;
+
+                if !force && !confirm(&format!("Delete entry \"{}\"? [y/N] ", entry.data().name()))?
+                {
                     return Ok(());
                 }
+                db.delete_entry(entry_id)?;
+
+                return Ok(());
+            }
+
+            RmCommand::Group {
+                group,
+                recursive,
+                force,
+            } => {
+                let mut db = open_database(db_path, &credentials, unlock_key_file.as_deref())?;
+                let group_id = match group {
+                    Some(group) => db.resolve_group(&group)?,
+                    None => db.resolve_group(&prompt_item("- group: ")?)?,
+                };
+                let group = db.entry_group(group_id)?;
+
+                let prompt = if recursive {
+                    format!(
+                        "Delete group \"{}\" with all its groups and entries? [y/N] ",
+                        group.data().name()
+                    )
+                } else {
+                    format!("Delete group \"{}\"? [y/N] ", group.data().name())
+                };
+                if !force && !confirm(&prompt)? {
+                    return Ok(());
+                }
+                db.delete_entry_group(group_id, recursive)?;
+
+                return Ok(());
+            }
+        },
+
+        Commands::Mv { command } => match command {
+            MvCommand::Entry { entry, group } => {
+                let db = open_database(db_path, &credentials, unlock_key_file.as_deref())?;
+                let entry_id = match entry {
+                    Some(entry) => db.resolve_entry(&entry)?,
+                    None => db.resolve_entry(&prompt_item("- entry: ")?)?,
+                };
+                let group_id = match group {
+                    Some(group) => db.resolve_group(&group)?,
+                    None => prompt_group_id(&db, "- destination group (leave empty for root): ")?,
+                };
+
+                db.move_entry(entry_id, group_id)?;
+
+                return Ok(());
+            }
+
+            MvCommand::Group {
+                group,
+                parent_group,
+            } => {
+                let mut db = open_database(db_path, &credentials, unlock_key_file.as_deref())?;
+                let group_id = match group {
+                    Some(group) => db.resolve_group(&group)?,
+                    None => db.resolve_group(&prompt_item("- group: ")?)?,
+                };
+                let parent_group_id = match parent_group {
+                    Some(parent_group) => db.resolve_group(&parent_group)?,
+                    None => prompt_group_id(&db, "- new parent group (leave empty for root): ")?,
+                };
+
+                db.move_entry_group(group_id, parent_group_id)?;
+
+                return Ok(());
+            }
+        },
+
+        Commands::Generate {
+            command: Some(GenerateCommand::Passphrase { .. }),
+            ..
+        } => unreachable!("handled before reading credentials"),
+
+        Commands::Generate {
+            command: None,
+            generator,
+        } => {
+            let db = open_database(db_path, &credentials, unlock_key_file.as_deref())?;
+            let policy = generator.password_policy(Some(&db))?;
+            println!("{}", generate_password(&policy)?.expose());
+
+            return Ok(());
+        }
+
+        Commands::Policy { command } => {
+            let db = open_database(db_path, &credentials, unlock_key_file.as_deref())?;
+
+            match command {
+                PolicyCommand::List => {
+                    for (name, policy) in db.policies()? {
+                        println!(
+                            "{}\t{}\t{:.0} bits",
+                            name,
+                            describe_policy(&policy),
+                            policy.entropy_bits()?
+                        );
+                    }
+                }
+
+                PolicyCommand::Save { name, generator } => {
+                    let policy = generator.password_policy(Some(&db))?;
+                    // reject policies that cannot generate any password
+                    policy.entropy_bits()?;
+                    db.save_policy(&name, &policy)?;
+                }
+
+                PolicyCommand::Remove { name } => db.remove_policy(&name)?,
+            }
+
+            return Ok(());
+        }
+
+        Commands::Tags => {
+            let db = open_database(db_path, &credentials, unlock_key_file.as_deref())?;
+            for (tag, count) in db.tags()? {
+                println!("{}\t{}", tag, count);
+            }
+
+            return Ok(());
+        }
+
+        Commands::Attach { command } => {
+            let db = open_database(db_path, &credentials, unlock_key_file.as_deref())?;
+
+            match command {
+                AttachCommand::List { entry } => {
+                    for attachment in db.attachments(db.resolve_entry(&entry)?)? {
+                        println!("{}\t{}", attachment.name(), attachment.size());
+                    }
+                }
+
+                AttachCommand::Add { entry, file, name } => {
+                    let entry_id = db.resolve_entry(&entry)?;
+                    let from_stdin = file == Path::new("-");
+                    let name = match name {
+                        Some(name) => name,
+                        None if from_stdin => {
+                            return Err(Error::InvalidInput(
+                                "attachments read from stdin need a --name".into(),
+                            ))
+                        }
+                        None => file
+                            .file_name()
+                            .and_then(|name| name.to_str())
+                            .ok_or_else(|| {
+                                Error::InvalidInput(format!(
+                                    "cannot name the attachment after {}, see --name",
+                                    file.display()
+                                ))
+                            })?
+                            .to_string(),
+                    };
+                    if from_stdin {
+                        db.insert_attachment(entry_id, &name, io::stdin().lock())?;
+                    } else {
+                        db.insert_attachment(entry_id, &name, std::fs::File::open(&file)?)?;
+                    }
+                }
+
+                AttachCommand::Get {
+                    entry,
+                    name,
+                    output,
+                } => {
+                    let entry_id = db.resolve_entry(&entry)?;
+                    match output {
+                        Some(output) => {
+                            let mut file = create_private_file(&output)?;
+                            let result = db.read_attachment(entry_id, &name, &mut file);
+                            if result.is_err() {
+                                // do not leave a partial file behind
+                                drop(file);
+                                let _ = std::fs::remove_file(&output);
+                            }
+                            result?;
+                        }
+                        None => {
+                            db.read_attachment(entry_id, &name, io::stdout().lock())?;
+                        }
+                    }
+                }
+
+                AttachCommand::Remove { entry, name } => {
+                    db.delete_attachment(db.resolve_entry(&entry)?, &name)?
+                }
             }
+
+            return Ok(());
         }
 
+        Commands::Keyfile { .. } => unreachable!("handled before reading credentials"),
+
         Commands::Gen { target } => match target {
             GenTarget::Zsh { cmd_name } => {
+                let cmd_name = cmd_name.to_str().ok_or_else(|| {
+                    Error::InvalidInput(format!("invalid command name {:?}", cmd_name))
+                })?;
                 clap_complete::generate(
                     Shell::Zsh,
                     &mut CliArgs::command(),
-                    cmd_name.to_str().unwrap(),
+                    cmd_name,
                     &mut io::stdout(),
                 );
 
@@ -324,12 +1844,337 @@ pub fn run(args: CliArgs) -> Result<(), ()> {
     }
 }
 
-fn read_password(show_password: bool) -> String {
+/// Vault given with `--vault` or `PASSWORD_CLI_VAULT`, otherwise the default
+/// vault of the user
+fn vault_path(vault: Option<PathBuf>, user: &str) -> Result<PathBuf> {
+    return vault_path_from(vault, user, |name| env::var(name).ok(), dirs::data_dir());
+}
+
+/// `vault` if given, otherwise the vault of the given user:
+/// `<DB_DIR><user><DB_SUFFIX>` if `DB_DIR` is set, otherwise `<user>.sqlite`
+/// in the user data directory `data_dir`, e.g. `~/.local/share/password-cli/`
+/// on Linux. Environment variables are read with `env_var`.
+fn vault_path_from(
+    vault: Option<PathBuf>,
+    user: &str,
+    env_var: impl Fn(&str) -> Option<String>,
+    data_dir: Option<PathBuf>,
+) -> Result<PathBuf> {
+    if let Some(vault) = vault {
+        return Ok(vault);
+    }
+
+    let db_suffix = env_var("DB_SUFFIX").unwrap_or_else(|| DEFAULT_VAULT_SUFFIX.into());
+    let file_name = format!("{}{}", user, db_suffix);
+
+    if let Some(db_dir) = env_var("DB_DIR") {
+        // DB_DIR is a prefix, "" meaning the current directory
+        return Ok(PathBuf::from(format!("{}{}", db_dir, file_name)));
+    }
+
+    let data_dir = data_dir.ok_or_else(|| {
+        Error::InvalidInput("no user data directory, use --vault or PASSWORD_CLI_VAULT".into())
+    })?;
+    return Ok(data_dir.join(env!("CARGO_PKG_NAME")).join(file_name));
+}
+
+/// Record that secrets of the entries were printed. A failure, e.g. on a
+/// read-only vault, is only reported: the read itself succeeded.
+fn record_entry_access(db: &Database, entry_ids: &[i64]) {
+    if let Err(err) = db.record_access(entry_ids) {
+        eprintln!(
+            "warning: could not record the access to the entries: {}",
+            err
+        );
+    }
+}
+
+/// Open the vault with the password, or with a key file if given. Legacy
+/// vaults are offered an upgrade to key slots if stdin is a terminal.
+fn open_database(
+    db_path: impl AsRef<Path>,
+    credentials: &Credentials,
+    unlock_key_file: Option<&Path>,
+) -> Result<Database> {
+    if let Some(key_file_path) = unlock_key_file {
+        let key_file = Zeroizing::new(std::fs::read(key_file_path)?);
+        return Database::open_with_key_file(db_path, key_file.as_slice());
+    }
+
+    let mut db = match Database::open(db_path.as_ref(), credentials) {
+        Err(Error::KeyFileRequired) if io::stdin().is_terminal() => {
+            eprintln!("this vault requires a key file on top of the password");
+            let key_file_path = prompt_line("Key file: ")?;
+            let key_file = Zeroizing::new(std::fs::read(key_file_path)?);
+            let credentials =
+                Credentials::new(credentials.user().into(), credentials.password().into())
+                    .with_key_file(key_file.as_slice());
+            Database::open(db_path.as_ref(), &credentials)?
+        }
+        result => result?,
+    };
+
+    if db.is_legacy() {
+        eprintln!("warning: this vault encrypts its data directly with the password key");
+
+        if io::stdin().is_terminal()
+            && confirm("Upgrade it to an Argon2id-wrapped data key now? [y/N] ")?
+        {
+            db.upgrade(credentials, KdfParams::recommended())?;
+            eprintln!("upgraded the vault to a wrapped data key");
+        }
+    }
+
+    return Ok(db);
+}
+
+/// Create a file readable only by its owner, refusing to overwrite an
+/// existing file
+fn create_private_file(path: &Path) -> Result<std::fs::File> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    return Ok(options.open(path)?);
+}
+
+/// Write a random key file, refusing to overwrite an existing file
+fn write_key_file(path: &Path) -> Result<()> {
+    let mut file = create_private_file(path)?;
+    file.write_all(KeyFileHash::generate_key_file().as_slice())?;
+    eprintln!(
+        "wrote a new key file to {}, keep a backup: the vaults requiring it cannot be opened without it",
+        path.display()
+    );
+
+    return Ok(());
+}
+
+/// Prompt for a new master password and replace the master password slot
+/// with it, keeping the KDF costs of the unlocked slot
+fn reset_master_password(
+    db: &mut Database,
+    credentials: &Credentials,
+    show_password: bool,
+) -> Result<()> {
+    eprint!("New master password: ");
+    let new_password = read_password(show_password)?;
+    eprint!("Confirm new master password: ");
+    if read_password(show_password)? != new_password {
+        return Err(Error::InvalidInput("passwords do not match".into()));
+    }
+
+    let mut new_credentials = Credentials::new(credentials.user().into(), new_password);
+    if let Some(key_file) = credentials.key_file() {
+        new_credentials = new_credentials.with_key_file_hash(key_file.clone());
+    }
+    let new_kdf = match db.kdf() {
+        Some(kdf) => {
+            KdfParams::with_random_salt(kdf.memory_kib(), kdf.iterations(), kdf.parallelism())
+        }
+        None => KdfParams::recommended(),
+    };
+    return db.reset_master_password(&new_credentials, new_kdf);
+}
+
+/// Add a new recovery key to the vault and print it, also writing it to the
+/// emergency kit file opened at the given path if any
+fn issue_recovery_key(
+    db: &Database,
+    db_path: &Path,
+    user: &str,
+    kdf: &KdfParams,
+    emergency_kit: Option<(&Path, std::fs::File)>,
+) -> Result<()> {
+    let recovery_key = db.add_recovery_key(kdf)?;
+    eprintln!("recovery key, it unlocks the vault without the password, keep it offline:");
+    println!("{}", recovery_key.to_printable().expose());
+
+    if let Some((path, mut file)) = emergency_kit {
+        let html = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("html"));
+        let vault_path = std::fs::canonicalize(db_path).unwrap_or_else(|_| db_path.into());
+        let kit = recovery::emergency_kit(
+            &vault_path,
+            user,
+            db.requires_key_file()?,
+            &recovery_key,
+            html,
+        );
+        file.write_all(kit.as_bytes())?;
+        eprintln!("wrote the emergency kit to {}", path.display());
+    }
+
+    return Ok(());
+}
+
+/// Print `prompt` to stdout and read a trimmed line from stdin
+fn prompt_line(prompt: &str) -> Result<String> {
+    let mut stdout = io::stdout();
+    print!("{}", prompt);
+    stdout.flush()?;
+
+    let mut line = String::new();
+    if io::stdin().read_line(&mut line)? == 0 {
+        return Err(Error::InvalidInput("unexpected end of input".into()));
+    }
+    return Ok(line.trim().to_string());
+}
+
+/// Prompt for the id or the path of an entry or a group
+fn prompt_item(prompt: &str) -> Result<ItemRef> {
+    let item = prompt_line(prompt)?;
+    if item.is_empty() {
+        return Err(Error::InvalidInput("missing id or path".into()));
+    }
+
+    return Ok(ItemRef::from(item));
+}
+
+/// Prompt for the id or the path of a group, an empty answer meaning the
+/// root group
+fn prompt_group_id(db: &Database, prompt: &str) -> Result<i64> {
+    let group = prompt_line(prompt)?;
+    if group.is_empty() {
+        return Ok(db.root_group_id());
+    }
+
+    return db.resolve_group(&ItemRef::from(group));
+}
+
+/// Generate a password and report its estimated entropy on stderr
+fn generate_password(policy: &PasswordPolicy) -> Result<SecretString> {
+    let password = policy.generate()?;
+    eprintln!("estimated entropy: {:.0} bits", policy.entropy_bits()?);
+    return Ok(password);
+}
+
+/// Short human-readable summary of a policy, e.g. `16 chars, a-z A-Z 0-9 "-_"`
+fn describe_policy(policy: &PasswordPolicy) -> String {
+    let mut description = format!("{} chars,", policy.length());
+    if policy.lowercase() {
+        description.push_str(" a-z");
+    }
+    if policy.uppercase() {
+        description.push_str(" A-Z");
+    }
+    if policy.digits() {
+        description.push_str(" 0-9");
+    }
+    if !policy.symbols().is_empty() {
+        description.push_str(&format!(" {:?}", policy.symbols()));
+    }
+    if policy.exclude_ambiguous() {
+        description.push_str(", no ambiguous");
+    }
+    if !policy.excluded().is_empty() {
+        description.push_str(&format!(", excluding {:?}", policy.excluded()));
+    }
+    return description;
+}
+
+fn parse_date(value: &str) -> std::result::Result<u64, String> {
+    return timestamp::parse(value, timestamp::now()).map_err(|err| err.to_string());
+}
+
+fn is_valid_name(name: &str) -> bool {
+    return name.chars().all(|ch| ch == ' ' || ch.is_ascii_graphic());
+}
+
+fn confirm(prompt: &str) -> Result<bool> {
+    let mut stderr = io::stderr();
+    stderr.write_all(prompt.as_bytes())?;
+    stderr.flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    return Ok(matches!(answer.trim(), "y" | "Y" | "yes"));
+}
+
+fn read_password(show_password: bool) -> Result<SecretString> {
     if show_password {
-        let mut password = String::new();
-        io::stdin().read_line(&mut password).unwrap();
-        return password.lines().next().unwrap().into();
+        let mut password = Zeroizing::new(String::new());
+        io::stdin().read_line(&mut password)?;
+        return Ok(password.lines().next().unwrap_or_default().into());
     } else {
-        return rpassword::read_password().unwrap();
+        return Ok(rpassword::read_password()?.into());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, path::PathBuf};
+
+    use clap::Parser;
+
+    use super::{vault_path_from, CliArgs, Commands, EditCommand};
+
+    #[test]
+    fn vault_path_precedence() {
+        let data_dir = || Some(PathBuf::from("/data"));
+        let default_vault = |vars: &[(&str, &str)]| {
+            let vars: HashMap<&str, &str> = vars.iter().copied().collect();
+            let env_var = |name: &str| vars.get(name).map(|value| value.to_string());
+            return vault_path_from(None, "bob", env_var, data_dir()).unwrap();
+        };
+        assert_eq!(
+            default_vault(&[]),
+            PathBuf::from("/data/password-cli/bob.sqlite")
+        );
+        assert_eq!(
+            default_vault(&[("DB_SUFFIX", ".db")]),
+            PathBuf::from("/data/password-cli/bob.db")
+        );
+        assert_eq!(
+            default_vault(&[("DB_SUFFIX", ".db"), ("DB_DIR", "vaults/")]),
+            PathBuf::from("vaults/bob.db")
+        );
+        assert_eq!(
+            default_vault(&[("DB_SUFFIX", ".db"), ("DB_DIR", "")]),
+            PathBuf::from("bob.db")
+        );
+        assert!(vault_path_from(None, "bob", |_| None, None).is_err());
+
+        let args =
+            CliArgs::try_parse_from(["password-cli", "--vault", "/arg.sqlite", "create"]).unwrap();
+        let env_var = |_: &str| Some("vaults/".to_string());
+        assert_eq!(
+            vault_path_from(args.vault, "bob", env_var, data_dir()).unwrap(),
+            PathBuf::from("/arg.sqlite")
+        );
+    }
+
+    #[test]
+    fn generator_options_require_generate() {
+        let check = |args: &[&str]| {
+            let args = CliArgs::try_parse_from(args).unwrap();
+            let Commands::Edit {
+                command:
+                    EditCommand::Entry {
+                        generate,
+                        generator,
+                        ..
+                    },
+            } = args.command
+            else {
+                panic!("not an entry edit");
+            };
+            return generator.check_generate(generate);
+        };
+        check(&["password-cli", "edit", "entry", "foo"]).unwrap();
+        check(&["password-cli", "edit", "entry", "foo", "--length", "32"]).unwrap_err();
+        check(&["password-cli", "edit", "entry", "foo", "--policy", "site"]).unwrap_err();
+        check(&[
+            "password-cli",
+            "edit",
+            "entry",
+            "foo",
+            "--generate",
+            "--length",
+            "32",
+        ])
+        .unwrap();
     }
 }
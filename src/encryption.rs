@@ -1,17 +1,22 @@
+use std::{fmt, str::FromStr};
+
 use aes_gcm::{
-    aead::{consts::U12, Aead, AeadCore, KeyInit, OsRng},
-    Aes256Gcm, Nonce,
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm,
 };
+use argon2::{Algorithm, Argon2, AssociatedData, ParamsBuilder, Version};
+use chacha20poly1305::XChaCha20Poly1305;
+use zeroize::{Zeroize, Zeroizing};
 
-pub const NONCE_SIZE: usize = 12usize;
+pub const SALT_SIZE: usize = 16usize;
 
 pub struct EncryptedMessage {
-    nonce: Nonce<U12>,
+    nonce: Vec<u8>,
     ciphertext: Vec<u8>,
 }
 
 impl EncryptedMessage {
-    fn new(nonce: Nonce<U12>, ciphertext: Vec<u8>) -> Self {
+    fn new(nonce: Vec<u8>, ciphertext: Vec<u8>) -> Self {
         Self { nonce, ciphertext }
     }
 
@@ -19,8 +24,9 @@ impl EncryptedMessage {
         self.ciphertext.as_ref()
     }
 
-    pub fn nonce(&self) -> Nonce<U12> {
-        self.nonce
+    /// Random nonce, of the size of the algorithm's nonces
+    pub fn nonce(&self) -> &[u8] {
+        self.nonce.as_ref()
     }
 }
 
@@ -33,7 +39,8 @@ impl From<aes_gcm::Error> for EncryptError {
     }
 }
 
-pub type DecryptedMessage = Vec<u8>;
+/// Plaintext, zeroed when dropped
+pub type DecryptedMessage = Zeroizing<Vec<u8>>;
 
 #[derive(Debug)]
 pub struct DecryptError;
@@ -44,11 +51,34 @@ impl From<aes_gcm::Error> for DecryptError {
     }
 }
 
-pub struct CipherKey([u8; 32]); 
+/// A 256-bit key. It lives in its own heap allocation, so that it is never
+/// copied around, which is locked in RAM when the platform allows it and
+/// zeroed when dropped.
+pub struct CipherKey(Box<[u8; 32]>);
 
 impl CipherKey {
-    pub fn new(bytes: [u8; 32]) -> Self {
-        return Self(bytes);
+    pub const SIZE: usize = 32;
+
+    /// Move `bytes` into a new key, zeroing the argument's copy.
+    pub fn new(mut bytes: [u8; 32]) -> Self {
+        let mut key = Self::zeroed();
+        key.0.copy_from_slice(&bytes);
+        bytes.zeroize();
+        return key;
+    }
+
+    /// Generate a random key with the OS random number generator.
+    pub fn generate() -> Self {
+        let mut key = Self::zeroed();
+        OsRng.fill_bytes(key.0.as_mut_slice());
+        return key;
+    }
+
+    /// Locked all-zero key, to be filled in place
+    fn zeroed() -> Self {
+        let key = Self(Box::new([0u8; 32]));
+        lock_memory(key.0.as_slice());
+        return key;
     }
 }
 
@@ -58,42 +88,350 @@ impl From<&[u8]> for CipherKey {
     }
 }
 
+impl Drop for CipherKey {
+    fn drop(&mut self) {
+        self.0.zeroize();
+        unlock_memory(self.0.as_slice());
+    }
+}
+
+impl fmt::Debug for CipherKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.write_str("CipherKey([redacted])");
+    }
+}
+
 impl AsRef<[u8; 32]> for CipherKey {
     fn as_ref(&self) -> &[u8; 32] {
         return &self.0;
     }
 }
 
+#[derive(Debug)]
+pub struct KdfError;
+
+impl From<argon2::Error> for KdfError {
+    fn from(_: argon2::Error) -> Self {
+        return Self;
+    }
+}
+
+/// Salt and Argon2id cost parameters used to derive a vault's key. They are
+/// stored in clear next to the vault, the salt being random for each vault.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KdfParams {
+    salt: [u8; SALT_SIZE],
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+}
+
+impl KdfParams {
+    pub const DEFAULT_MEMORY_KIB: u32 = 64 * 1024;
+    pub const DEFAULT_ITERATIONS: u32 = 3;
+    pub const DEFAULT_PARALLELISM: u32 = 4;
+
+    pub fn new(salt: [u8; SALT_SIZE], memory_kib: u32, iterations: u32, parallelism: u32) -> Self {
+        Self {
+            salt,
+            memory_kib,
+            iterations,
+            parallelism,
+        }
+    }
+
+    /// Default cost parameters with a new random salt.
+    pub fn recommended() -> Self {
+        return Self::with_random_salt(
+            Self::DEFAULT_MEMORY_KIB,
+            Self::DEFAULT_ITERATIONS,
+            Self::DEFAULT_PARALLELISM,
+        );
+    }
+
+    /// Use the given cost parameters with a new random salt.
+    pub fn with_random_salt(memory_kib: u32, iterations: u32, parallelism: u32) -> Self {
+        let mut salt = [0u8; SALT_SIZE];
+        OsRng.fill_bytes(&mut salt);
+        return Self::new(salt, memory_kib, iterations, parallelism);
+    }
+
+    pub fn salt(&self) -> &[u8; SALT_SIZE] {
+        &self.salt
+    }
+
+    pub fn memory_kib(&self) -> u32 {
+        self.memory_kib
+    }
+
+    pub fn iterations(&self) -> u32 {
+        self.iterations
+    }
+
+    pub fn parallelism(&self) -> u32 {
+        self.parallelism
+    }
+
+    /// Derive a key from `password` with Argon2id. `associated_data` is
+    /// mixed into the hash without being secret (e.g. a username).
+    pub fn derive_key(
+        &self,
+        password: &[u8],
+        associated_data: &[u8],
+    ) -> Result<CipherKey, KdfError> {
+        return self.derive_key_with_secret(password, None, associated_data);
+    }
+
+    /// Same as [`KdfParams::derive_key`], with `secret` given to Argon2id as
+    /// its secret input, e.g. the hash of a key file required on top of the
+    /// password.
+    pub fn derive_key_with_secret(
+        &self,
+        password: &[u8],
+        secret: Option<&[u8]>,
+        associated_data: &[u8],
+    ) -> Result<CipherKey, KdfError> {
+        let params = ParamsBuilder::new()
+            .m_cost(self.memory_kib)
+            .t_cost(self.iterations)
+            .p_cost(self.parallelism)
+            .data(AssociatedData::new(associated_data)?)
+            .build()?;
+        let argon2 = match secret {
+            Some(secret) => {
+                Argon2::new_with_secret(secret, Algorithm::Argon2id, Version::V0x13, params)?
+            }
+            None => Argon2::new(Algorithm::Argon2id, Version::V0x13, params),
+        };
+
+        let mut key = CipherKey::zeroed();
+        argon2.hash_password_into(password, &self.salt, key.0.as_mut_slice())?;
+
+        return Ok(key);
+    }
+}
+
+/// SHA-256 hash of a key file's content, required on top of the password
+/// to unlock vaults that use a key file as a second factor.
+#[derive(Clone)]
+pub struct KeyFileHash(Zeroizing<[u8; 32]>);
+
+impl KeyFileHash {
+    /// Size of the key files written by [`KeyFileHash::generate_key_file`]
+    pub const KEY_FILE_SIZE: usize = 64;
+
+    pub fn new(key_file: &[u8]) -> Self {
+        return Self(Zeroizing::new(hmac_sha256::Hash::hash(key_file)));
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_slice()
+    }
+
+    /// Random content for a new key file
+    pub fn generate_key_file() -> Zeroizing<Vec<u8>> {
+        let mut key_file = Zeroizing::new(vec![0u8; Self::KEY_FILE_SIZE]);
+        OsRng.fill_bytes(key_file.as_mut_slice());
+        return key_file;
+    }
+}
+
+impl fmt::Debug for KeyFileHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.write_str("KeyFileHash([redacted])");
+    }
+}
+
+/// AEAD algorithm encrypting a vault's data, chosen per vault
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CipherAlgorithm {
+    /// AES-256-GCM, with random 96-bit nonces
+    #[default]
+    Aes256Gcm,
+    /// XChaCha20-Poly1305, with random 192-bit nonces which never collide in
+    /// practice, and fast without AES instructions
+    XChaCha20Poly1305,
+}
+
+impl CipherAlgorithm {
+    pub const ALL: [Self; 2] = [Self::Aes256Gcm, Self::XChaCha20Poly1305];
+
+    pub fn from_id(id: i64) -> Option<Self> {
+        match id {
+            0 => Some(Self::Aes256Gcm),
+            1 => Some(Self::XChaCha20Poly1305),
+            _ => None,
+        }
+    }
+
+    /// Identifier stored in the vault
+    pub fn id(self) -> i64 {
+        match self {
+            Self::Aes256Gcm => 0,
+            Self::XChaCha20Poly1305 => 1,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Aes256Gcm => "aes256gcm",
+            Self::XChaCha20Poly1305 => "xchacha20",
+        }
+    }
+
+    pub fn nonce_size(self) -> usize {
+        match self {
+            Self::Aes256Gcm => 12,
+            Self::XChaCha20Poly1305 => 24,
+        }
+    }
+}
+
+impl fmt::Display for CipherAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.write_str(self.name());
+    }
+}
+
+impl FromStr for CipherAlgorithm {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        return Self::ALL
+            .into_iter()
+            .find(|algorithm| algorithm.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(|algorithm| algorithm.name()).collect();
+                format!("unknown cipher, expected one of: {}", names.join(", "))
+            });
+    }
+}
+
+/// An AEAD and its key. The key is kept so that blobs written with another
+/// algorithm can be read with [`Cipher::for_algorithm`].
 pub struct Cipher {
-    aes: Aes256Gcm,
+    aead: AeadImpl,
+    key: CipherKey,
+}
+
+/// The AES key schedule is boxed, being much larger than ChaCha's key
+enum AeadImpl {
+    Aes256Gcm(Box<Aes256Gcm>),
+    XChaCha20Poly1305(XChaCha20Poly1305),
 }
 
 impl Cipher {
+    /// AES-256-GCM cipher, used for key slots
     pub fn new(key: &CipherKey) -> Self {
-        let key: &[u8; 32] = key.as_ref();
-        return Self {
-            aes: Aes256Gcm::new(key.into()),
+        return Self::with_algorithm(key, CipherAlgorithm::Aes256Gcm);
+    }
+
+    pub fn with_algorithm(key: &CipherKey, algorithm: CipherAlgorithm) -> Self {
+        let key = CipherKey::new(*key.as_ref());
+        let key_bytes: &[u8; 32] = key.as_ref();
+        let aead = match algorithm {
+            CipherAlgorithm::Aes256Gcm => {
+                AeadImpl::Aes256Gcm(Box::new(Aes256Gcm::new(key_bytes.into())))
+            }
+            CipherAlgorithm::XChaCha20Poly1305 => {
+                AeadImpl::XChaCha20Poly1305(XChaCha20Poly1305::new(key_bytes.into()))
+            }
         };
+        return Self { aead, key };
+    }
+
+    /// Cipher with the same key and another algorithm
+    pub fn for_algorithm(&self, algorithm: CipherAlgorithm) -> Self {
+        return Self::with_algorithm(&self.key, algorithm);
     }
 
-    pub fn encrypt(&self, plaintext: &[u8]) -> Result<EncryptedMessage, EncryptError> {
-        let nonce = Aes256Gcm::generate_nonce(&mut OsRng); // 96-bits; unique per message
-        let ciphertext = self.aes.encrypt(&nonce, plaintext.as_ref())?;
+    pub fn algorithm(&self) -> CipherAlgorithm {
+        match self.aead {
+            AeadImpl::Aes256Gcm(_) => CipherAlgorithm::Aes256Gcm,
+            AeadImpl::XChaCha20Poly1305(_) => CipherAlgorithm::XChaCha20Poly1305,
+        }
+    }
+
+    /// Encrypt `plaintext` with a random nonce. `associated_data` is
+    /// authenticated but not encrypted, decryption fails unless the same
+    /// associated data is given.
+    pub fn encrypt(
+        &self,
+        plaintext: &[u8],
+        associated_data: &[u8],
+    ) -> Result<EncryptedMessage, EncryptError> {
+        let payload = Payload {
+            msg: plaintext,
+            aad: associated_data,
+        };
+        let (nonce, ciphertext) = match &self.aead {
+            AeadImpl::Aes256Gcm(aes) => {
+                let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+                (nonce.to_vec(), aes.encrypt(&nonce, payload)?)
+            }
+            AeadImpl::XChaCha20Poly1305(chacha) => {
+                let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+                (nonce.to_vec(), chacha.encrypt(&nonce, payload)?)
+            }
+        };
 
         return Ok(EncryptedMessage::new(nonce, ciphertext));
     }
 
+    /// Fails if `nonce` is not of the algorithm's nonce size.
     pub fn decrypt(
         &self,
         ciphertext: &[u8],
-        nonce: &Nonce<U12>,
+        nonce: &[u8],
+        associated_data: &[u8],
     ) -> Result<DecryptedMessage, DecryptError> {
-        let plaintext = self.aes.decrypt(nonce, ciphertext.as_ref())?;
+        if nonce.len() != self.algorithm().nonce_size() {
+            return Err(DecryptError);
+        }
+        let payload = Payload {
+            msg: ciphertext,
+            aad: associated_data,
+        };
+        let plaintext = match &self.aead {
+            AeadImpl::Aes256Gcm(aes) => aes.decrypt(nonce.into(), payload)?,
+            AeadImpl::XChaCha20Poly1305(chacha) => chacha.decrypt(nonce.into(), payload)?,
+        };
+
+        return Ok(Zeroizing::new(plaintext));
+    }
+}
 
-        return Ok(plaintext);
+/// Keep the pages holding `bytes` in RAM so that they are never written to
+/// swap. This is best effort: locking fails beyond `RLIMIT_MEMLOCK`, and the
+/// error is only logged.
+#[cfg(unix)]
+fn lock_memory(bytes: &[u8]) {
+    // SAFETY: mlock only reads the address range, which is a valid slice
+    let result = unsafe { libc::mlock(bytes.as_ptr().cast(), bytes.len()) };
+    if result != 0 {
+        log::debug!(
+            "could not lock key in memory: {}",
+            std::io::Error::last_os_error()
+        );
     }
 }
 
+#[cfg(not(unix))]
+fn lock_memory(_: &[u8]) {}
+
+/// Undo [`lock_memory`]. Page locks do not stack, so this may unlock another
+/// key sharing the same page, which is then only zeroed on drop.
+#[cfg(unix)]
+fn unlock_memory(bytes: &[u8]) {
+    // SAFETY: munlock only reads the address range, which is a valid slice
+    unsafe {
+        libc::munlock(bytes.as_ptr().cast(), bytes.len());
+    }
+}
+
+#[cfg(not(unix))]
+fn unlock_memory(_: &[u8]) {}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -102,13 +440,34 @@ mod test {
     fn can_decrypt() {
         let secret = b"my super secret key";
         let og_plaintext = b"plaintext message";
-        let cipher = Cipher::new(&secret.as_slice().into());
-        let msg = cipher.encrypt(og_plaintext).expect("encrypt error");
-        let plaintext = cipher
-            .decrypt(&msg.ciphertext, &msg.nonce)
-            .expect("decrypt error");
+        for algorithm in CipherAlgorithm::ALL {
+            let cipher = Cipher::with_algorithm(&secret.as_slice().into(), algorithm);
+            let msg = cipher.encrypt(og_plaintext, b"ad").expect("encrypt error");
+            assert_eq!(msg.nonce().len(), algorithm.nonce_size());
+            let plaintext = cipher
+                .decrypt(&msg.ciphertext, &msg.nonce, b"ad")
+                .expect("decrypt error");
 
-        assert_eq!(&plaintext, og_plaintext);
+            assert_eq!(plaintext.as_slice(), og_plaintext);
+        }
+    }
+
+    #[test]
+    fn different_algorithm_cant_decrypt() {
+        let key: CipherKey = b"my super secret key".as_slice().into();
+        let aes = Cipher::with_algorithm(&key, CipherAlgorithm::Aes256Gcm);
+        let chacha = Cipher::with_algorithm(&key, CipherAlgorithm::XChaCha20Poly1305);
+
+        let msg = aes
+            .encrypt(b"plaintext message", b"ad")
+            .expect("encrypt error");
+        chacha
+            .decrypt(&msg.ciphertext, &msg.nonce, b"ad")
+            .unwrap_err();
+        let msg = chacha
+            .encrypt(b"plaintext message", b"ad")
+            .expect("encrypt error");
+        aes.decrypt(&msg.ciphertext, &msg.nonce, b"ad").unwrap_err();
     }
 
     #[test]
@@ -116,10 +475,12 @@ mod test {
         let secret = b"my super secret key";
         let og_plaintext = b"plaintext message";
         let cipher = Cipher::new(&secret.as_slice().into());
-        let msg = cipher.encrypt(og_plaintext).expect("encrypt error");
+        let msg = cipher.encrypt(og_plaintext, b"ad").expect("encrypt error");
 
         let wrong_nonce = Aes256Gcm::generate_nonce(&mut OsRng);
-        cipher.decrypt(&msg.ciphertext, &wrong_nonce).unwrap_err();
+        cipher
+            .decrypt(&msg.ciphertext, wrong_nonce.as_slice(), b"ad")
+            .unwrap_err();
     }
 
     #[test]
@@ -127,12 +488,60 @@ mod test {
         let secret = b"my super secret key";
         let og_plaintext = b"plaintext message";
         let cipher = Cipher::new(&secret.as_slice().into());
-        let msg = cipher.encrypt(og_plaintext).expect("encrypt error");
+        let msg = cipher.encrypt(og_plaintext, b"ad").expect("encrypt error");
 
         let wrong_secret = b"1234";
         let wrong_cipher = Cipher::new(&wrong_secret.as_slice().into());
         wrong_cipher
-            .decrypt(&msg.ciphertext, &msg.nonce)
+            .decrypt(&msg.ciphertext, &msg.nonce, b"ad")
+            .unwrap_err();
+    }
+
+    #[test]
+    fn different_associated_data_cant_decrypt() {
+        let secret = b"my super secret key";
+        let og_plaintext = b"plaintext message";
+        let cipher = Cipher::new(&secret.as_slice().into());
+        let msg = cipher
+            .encrypt(og_plaintext, b"Entry 1")
+            .expect("encrypt error");
+
+        cipher
+            .decrypt(&msg.ciphertext, &msg.nonce, b"Entry 2")
             .unwrap_err();
     }
+
+    #[test]
+    fn kdf_depends_on_secret() {
+        let password = b"my super secret key";
+        let kdf = KdfParams::with_random_salt(64, 1, 1);
+        let key_file = KeyFileHash::new(b"key file");
+        let other_key_file = KeyFileHash::new(b"other key file");
+
+        let key = kdf
+            .derive_key_with_secret(password, Some(key_file.as_bytes()), b"user")
+            .expect("kdf error");
+        let other_key = kdf
+            .derive_key_with_secret(password, Some(other_key_file.as_bytes()), b"user")
+            .expect("kdf error");
+        let no_secret_key = kdf.derive_key(password, b"user").expect("kdf error");
+
+        assert_ne!(key.as_ref(), other_key.as_ref());
+        assert_ne!(key.as_ref(), no_secret_key.as_ref());
+    }
+
+    #[test]
+    fn kdf_depends_on_salt() {
+        let password = b"my super secret key";
+        let kdf = KdfParams::with_random_salt(64, 1, 1);
+        let same_kdf = KdfParams::new(*kdf.salt(), 64, 1, 1);
+        let other_kdf = KdfParams::with_random_salt(64, 1, 1);
+
+        let key = kdf.derive_key(password, b"user").expect("kdf error");
+        let same_key = same_kdf.derive_key(password, b"user").expect("kdf error");
+        let other_key = other_kdf.derive_key(password, b"user").expect("kdf error");
+
+        assert_eq!(key.as_ref(), same_key.as_ref());
+        assert_ne!(key.as_ref(), other_key.as_ref());
+    }
 }
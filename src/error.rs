@@ -0,0 +1,156 @@
+use std::{fmt, io, path::PathBuf};
+
+use crate::encryption::{DecryptError, EncryptError, KdfError};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The given password or key file does not unlock the vault
+    WrongCredentials,
+    VaultNotFound(PathBuf),
+    VaultAlreadyExists(PathBuf),
+    EntryNotFound(i64),
+    GroupNotFound(i64),
+    KeySlotNotFound(i64),
+    /// No group or entry at the given path
+    PathNotFound(String),
+    /// Several siblings share a name of the given path
+    AmbiguousPath(String),
+    PolicyNotFound(String),
+    /// No attachment with the given name on the entry
+    AttachmentNotFound(String),
+    /// The vault was created by a newer release, with this schema version
+    UnsupportedSchemaVersion(u32),
+    /// The vault requires a key file on top of the password, and none was
+    /// given
+    KeyFileRequired,
+    /// An encrypted blob could not be decrypted or has an invalid format
+    CorruptBlob,
+    /// Decrypted data could not be (de)serialized
+    Cbor(String),
+    /// Data could not be encrypted
+    Encryption,
+    Sqlite(rusqlite::Error),
+    Io(io::Error),
+    InvalidInput(String),
+}
+
+impl Error {
+    /// Process exit code reported by the CLI for this error, so that scripts
+    /// can tell errors apart. Codes start at 3, clap exiting with 2 on usage
+    /// errors.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::InvalidInput(_) => 3,
+            Self::WrongCredentials => 4,
+            Self::VaultNotFound(_) => 5,
+            Self::VaultAlreadyExists(_) => 6,
+            Self::EntryNotFound(_) => 7,
+            Self::GroupNotFound(_) => 8,
+            Self::KeySlotNotFound(_) => 9,
+            Self::CorruptBlob => 10,
+            Self::Cbor(_) => 11,
+            Self::Encryption => 12,
+            Self::Sqlite(_) => 13,
+            Self::Io(_) => 14,
+            Self::PathNotFound(_) => 15,
+            Self::AmbiguousPath(_) => 16,
+            Self::PolicyNotFound(_) => 17,
+            Self::UnsupportedSchemaVersion(_) => 18,
+            Self::KeyFileRequired => 19,
+            Self::AttachmentNotFound(_) => 20,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WrongCredentials => write!(f, "wrong credentials"),
+            Self::VaultNotFound(path) => write!(f, "vault not found: {}", path.display()),
+            Self::VaultAlreadyExists(path) => {
+                write!(f, "vault already exists: {}", path.display())
+            }
+            Self::EntryNotFound(id) => write!(f, "no entry with id {}", id),
+            Self::GroupNotFound(id) => write!(f, "no group with id {}", id),
+            Self::KeySlotNotFound(id) => write!(f, "no key slot with id {}", id),
+            Self::PathNotFound(path) => write!(f, "nothing found at path \"{}\"", path),
+            Self::PolicyNotFound(name) => write!(f, "no password policy named \"{}\"", name),
+            Self::AttachmentNotFound(name) => write!(f, "no attachment named \"{}\"", name),
+            Self::UnsupportedSchemaVersion(version) => write!(
+                f,
+                "unsupported vault schema version {}, created by a newer release",
+                version
+            ),
+            Self::KeyFileRequired => {
+                write!(f, "this vault also requires its key file, see --key-file")
+            }
+            Self::AmbiguousPath(path) => {
+                write!(
+                    f,
+                    "ambiguous path \"{}\": several siblings share a name",
+                    path
+                )
+            }
+            Self::CorruptBlob => write!(f, "corrupt or tampered encrypted data"),
+            Self::Cbor(message) => write!(f, "invalid serialized data: {}", message),
+            Self::Encryption => write!(f, "could not encrypt data"),
+            Self::Sqlite(err) => write!(f, "database error: {}", err),
+            Self::Io(err) => write!(f, "I/O error: {}", err),
+            Self::InvalidInput(message) => write!(f, "invalid input: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Sqlite(err) => Some(err),
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Self {
+        return Self::Sqlite(err);
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        return Self::Io(err);
+    }
+}
+
+impl From<ciborium::de::Error<io::Error>> for Error {
+    fn from(err: ciborium::de::Error<io::Error>) -> Self {
+        return Self::Cbor(err.to_string());
+    }
+}
+
+impl From<ciborium::ser::Error<io::Error>> for Error {
+    fn from(err: ciborium::ser::Error<io::Error>) -> Self {
+        return Self::Cbor(err.to_string());
+    }
+}
+
+impl From<DecryptError> for Error {
+    fn from(_: DecryptError) -> Self {
+        return Self::CorruptBlob;
+    }
+}
+
+impl From<EncryptError> for Error {
+    fn from(_: EncryptError) -> Self {
+        return Self::Encryption;
+    }
+}
+
+impl From<KdfError> for Error {
+    fn from(_: KdfError) -> Self {
+        return Self::InvalidInput("invalid key derivation parameters".into());
+    }
+}
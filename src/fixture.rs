@@ -0,0 +1,153 @@
+//! Vaults with known content for unit and integration tests. Integration
+//! tests need the `test-fixtures` feature.
+
+use std::path::Path;
+
+use crate::{
+    encryption::KdfParams,
+    model::{
+        database::Database,
+        path::{self, ItemRef},
+        Credentials, EntryData, EntryGroupData,
+    },
+    Error, Result,
+};
+
+/// Builds a vault with the given groups and entries. Its key slot uses cheap
+/// Argon2id parameters to keep tests fast.
+pub struct VaultBuilder {
+    user: String,
+    password: String,
+    root_group_name: String,
+    hide_structure: bool,
+    /// Paths of the groups to create, parents first
+    groups: Vec<String>,
+    /// Group path and data of the entries to create
+    entries: Vec<(String, EntryData)>,
+}
+
+impl VaultBuilder {
+    pub const USER: &'static str = "user";
+    pub const PASSWORD: &'static str = "password";
+
+    pub fn new() -> Self {
+        Self {
+            user: Self::USER.into(),
+            password: Self::PASSWORD.into(),
+            root_group_name: Database::DEFAULT_ROOT_GROUP_NAME.into(),
+            hide_structure: false,
+            groups: vec![],
+            entries: vec![],
+        }
+    }
+
+    pub fn credentials(mut self, user: &str, password: &str) -> Self {
+        self.user = user.into();
+        self.password = password.into();
+        return self;
+    }
+
+    pub fn root_group_name(mut self, name: &str) -> Self {
+        self.root_group_name = name.into();
+        return self;
+    }
+
+    /// Keep the group tree in the encrypted structure index, see
+    /// [`Database::set_structure_hidden`]
+    pub fn hide_structure(mut self) -> Self {
+        self.hide_structure = true;
+        return self;
+    }
+
+    /// Add a group and its missing ancestors, e.g. `Work/AWS`
+    pub fn group(mut self, group_path: &str) -> Self {
+        self.groups.push(group_path.into());
+        return self;
+    }
+
+    /// Add an entry to the group at the given path, "" being the root group.
+    /// Missing groups are created.
+    pub fn entry(mut self, group_path: &str, name: &str, username: &str, password: &str) -> Self {
+        if !group_path.is_empty() {
+            self.groups.push(group_path.into());
+        }
+        self.entries.push((
+            group_path.into(),
+            EntryData::new(name.into(), username.into(), password.into()),
+        ));
+        return self;
+    }
+
+    pub fn to_credentials(&self) -> Credentials {
+        return Credentials::new(self.user.as_str().into(), self.password.as_str().into());
+    }
+
+    /// Create the vault at `path`
+    pub fn build(self, db_path: impl AsRef<Path>) -> Result<Database> {
+        let mut db = Database::create(
+            db_path,
+            &self.to_credentials(),
+            KdfParams::with_random_salt(64, 1, 1),
+            &self.root_group_name,
+        )?;
+        db.set_structure_hidden(self.hide_structure)?;
+
+        for group_path in &self.groups {
+            let mut parent_id = db.root_group_id();
+            let mut prefix = vec![];
+            for name in path::split(group_path)? {
+                prefix.push(name.clone());
+                let prefix_ref = ItemRef::Path(path::join(prefix.iter().map(String::as_str)));
+                parent_id = match db.resolve_group(&prefix_ref) {
+                    Ok(group_id) => group_id,
+                    Err(Error::PathNotFound(_)) => {
+                        db.insert_entry_group(parent_id, &EntryGroupData::new(name.into()))?
+                    }
+                    Err(err) => return Err(err),
+                };
+            }
+        }
+
+        for (group_path, entry_data) in &self.entries {
+            let group_id = db.resolve_group(&ItemRef::Path(group_path.clone()))?;
+            db.insert_entry(group_id, entry_data)?;
+        }
+
+        return Ok(db);
+    }
+}
+
+impl Default for VaultBuilder {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::VaultBuilder;
+
+    #[test]
+    fn build_vault() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = VaultBuilder::new()
+            .root_group_name("vault")
+            .group("Personal")
+            .entry("Work/AWS", "prod-root", "admin", "secret")
+            .entry("", "email", "me", "hunter2")
+            .build(dir.path().join("test.sqlite"))
+            .unwrap();
+
+        let groups = db.groups(None).unwrap();
+        let mut names: Vec<&str> = groups.iter().map(|group| group.data().name()).collect();
+        names.sort_unstable();
+        assert_eq!(names, ["AWS", "Personal", "Work", "vault"]);
+        assert_eq!(db.entries(None, None).unwrap().len(), 2);
+        assert_eq!(
+            db.entries(None, Some(db.root_group_id())).unwrap()[0]
+                .data()
+                .name(),
+            "email"
+        );
+    }
+}
@@ -0,0 +1,370 @@
+use std::sync::OnceLock;
+
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::{secret::SecretString, Error, Result};
+
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+/// Characters easily mistaken for one another
+const AMBIGUOUS: &str = "0O1Il|";
+/// Diceware word list: 7776 lines made of five dice rolls, a tab and a word
+const WORDLIST: &str = include_str!("../data/wordlist.txt");
+
+/// Rules for generating random passwords. A password contains at least one
+/// character of each enabled class.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PasswordPolicy {
+    length: usize,
+    lowercase: bool,
+    uppercase: bool,
+    digits: bool,
+    /// Symbols to pick from, symbols are disabled if empty
+    symbols: String,
+    exclude_ambiguous: bool,
+    /// Characters that never appear in passwords
+    excluded: String,
+}
+
+impl PasswordPolicy {
+    pub const DEFAULT_LENGTH: usize = 20;
+    pub const DEFAULT_SYMBOLS: &'static str = "!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
+
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    pub fn set_length(&mut self, length: usize) {
+        self.length = length;
+    }
+
+    pub fn lowercase(&self) -> bool {
+        self.lowercase
+    }
+
+    pub fn set_lowercase(&mut self, lowercase: bool) {
+        self.lowercase = lowercase;
+    }
+
+    pub fn uppercase(&self) -> bool {
+        self.uppercase
+    }
+
+    pub fn set_uppercase(&mut self, uppercase: bool) {
+        self.uppercase = uppercase;
+    }
+
+    pub fn digits(&self) -> bool {
+        self.digits
+    }
+
+    pub fn set_digits(&mut self, digits: bool) {
+        self.digits = digits;
+    }
+
+    pub fn symbols(&self) -> &str {
+        self.symbols.as_ref()
+    }
+
+    pub fn set_symbols(&mut self, symbols: String) {
+        self.symbols = symbols;
+    }
+
+    pub fn exclude_ambiguous(&self) -> bool {
+        self.exclude_ambiguous
+    }
+
+    pub fn set_exclude_ambiguous(&mut self, exclude_ambiguous: bool) {
+        self.exclude_ambiguous = exclude_ambiguous;
+    }
+
+    pub fn excluded(&self) -> &str {
+        self.excluded.as_ref()
+    }
+
+    pub fn set_excluded(&mut self, excluded: String) {
+        self.excluded = excluded;
+    }
+
+    /// Characters of each enabled class, without the excluded ones
+    fn classes(&self) -> Result<Vec<Vec<char>>> {
+        let enabled = [
+            (self.lowercase, LOWERCASE),
+            (self.uppercase, UPPERCASE),
+            (self.digits, DIGITS),
+            (!self.symbols.is_empty(), self.symbols.as_str()),
+        ];
+
+        let mut classes = vec![];
+        for (_, chars) in enabled.iter().filter(|(enabled, _)| *enabled) {
+            let mut class: Vec<char> = chars
+                .chars()
+                .filter(|ch| !self.excluded.contains(*ch))
+                .filter(|ch| !(self.exclude_ambiguous && AMBIGUOUS.contains(*ch)))
+                .collect();
+            class.sort_unstable();
+            class.dedup();
+            if class.is_empty() {
+                return Err(Error::InvalidInput(format!(
+                    "every character of the class \"{}\" is excluded",
+                    chars
+                )));
+            }
+            classes.push(class);
+        }
+
+        if classes.is_empty() {
+            return Err(Error::InvalidInput(
+                "at least one character class must be enabled".into(),
+            ));
+        }
+        if self.length < classes.len() {
+            return Err(Error::InvalidInput(format!(
+                "a password of {} characters cannot contain all {} required classes",
+                self.length,
+                classes.len()
+            )));
+        }
+
+        return Ok(classes);
+    }
+
+    /// Generate a password with the OS random number generator.
+    pub fn generate(&self) -> Result<SecretString> {
+        let classes = self.classes()?;
+        let alphabet: Vec<char> = classes.iter().flatten().copied().collect();
+
+        let mut password: Zeroizing<Vec<char>> = Zeroizing::new(
+            classes
+                .iter()
+                .map(|class| class[random_index(class.len())])
+                .collect(),
+        );
+        while password.len() < self.length {
+            password.push(alphabet[random_index(alphabet.len())]);
+        }
+        // Fisher-Yates shuffle, so that required characters can be anywhere
+        for i in (1..password.len()).rev() {
+            password.swap(i, random_index(i + 1));
+        }
+
+        return Ok(SecretString::new(password.iter().collect()));
+    }
+
+    /// Estimated entropy of generated passwords in bits, as if every
+    /// character was picked uniformly from all enabled classes.
+    pub fn entropy_bits(&self) -> Result<f64> {
+        let alphabet_size: usize = self.classes()?.iter().map(Vec::len).sum();
+        return Ok(self.length as f64 * (alphabet_size as f64).log2());
+    }
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            length: Self::DEFAULT_LENGTH,
+            lowercase: true,
+            uppercase: true,
+            digits: true,
+            symbols: Self::DEFAULT_SYMBOLS.into(),
+            exclude_ambiguous: false,
+            excluded: String::new(),
+        }
+    }
+}
+
+/// Rules for generating diceware-style passphrases, e.g. `Lunar-Velvet-Otter7`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassphrasePolicy {
+    words: usize,
+    separator: String,
+    capitalize: bool,
+    /// Append a random digit to the last word
+    digit: bool,
+}
+
+impl PassphrasePolicy {
+    pub const DEFAULT_WORDS: usize = 6;
+    pub const DEFAULT_SEPARATOR: &'static str = "-";
+
+    pub fn new(words: usize, separator: String, capitalize: bool, digit: bool) -> Self {
+        Self {
+            words,
+            separator,
+            capitalize,
+            digit,
+        }
+    }
+
+    pub fn words(&self) -> usize {
+        self.words
+    }
+
+    /// Generate a passphrase with the OS random number generator.
+    pub fn generate(&self) -> Result<SecretString> {
+        if self.words == 0 {
+            return Err(Error::InvalidInput(
+                "a passphrase needs at least one word".into(),
+            ));
+        }
+
+        let wordlist = wordlist();
+        let mut words: Zeroizing<Vec<String>> = Zeroizing::new(
+            (0..self.words)
+                .map(|_| {
+                    let word = wordlist[random_index(wordlist.len())];
+                    if self.capitalize {
+                        let mut chars = word.chars();
+                        return chars
+                            .next()
+                            .map(|first| first.to_uppercase().chain(chars).collect())
+                            .unwrap_or_default();
+                    }
+                    return word.to_string();
+                })
+                .collect(),
+        );
+        if self.digit {
+            if let Some(last) = words.last_mut() {
+                last.push_str(&random_index(10).to_string());
+            }
+        }
+
+        return Ok(SecretString::new(words.join(&self.separator)));
+    }
+
+    /// Entropy of generated passphrases in bits, assuming the word list and
+    /// the options are known to an attacker.
+    pub fn entropy_bits(&self) -> f64 {
+        let mut bits = self.words as f64 * (wordlist().len() as f64).log2();
+        if self.digit {
+            bits += 10f64.log2();
+        }
+        return bits;
+    }
+}
+
+impl Default for PassphrasePolicy {
+    fn default() -> Self {
+        Self::new(
+            Self::DEFAULT_WORDS,
+            Self::DEFAULT_SEPARATOR.into(),
+            false,
+            false,
+        )
+    }
+}
+
+/// Words of the bundled diceware word list, parsed on first use
+pub fn wordlist() -> &'static [&'static str] {
+    static WORDS: OnceLock<Vec<&'static str>> = OnceLock::new();
+    return WORDS.get_or_init(|| {
+        WORDLIST
+            .lines()
+            .filter_map(|line| line.split('\t').nth(1))
+            .collect()
+    });
+}
+
+/// Uniformly random index in `0..bound`, using rejection sampling to avoid
+/// modulo bias.
+pub(crate) fn random_index(bound: usize) -> usize {
+    assert!(bound > 0 && bound <= u32::MAX as usize);
+    let bound = bound as u64;
+    let zone = (1u64 << 32) - (1u64 << 32) % bound;
+
+    loop {
+        let value = OsRng.next_u32() as u64;
+        if value < zone {
+            return (value % bound) as usize;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::{
+        wordlist, PassphrasePolicy, PasswordPolicy, DIGITS, LOWERCASE, UPPERCASE, WORDLIST,
+    };
+
+    #[test]
+    fn generated_password_follows_policy() {
+        let mut policy = PasswordPolicy::default();
+        policy.set_length(16);
+        policy.set_symbols("-_".into());
+        policy.set_excluded("abc".into());
+        policy.set_exclude_ambiguous(true);
+
+        for _ in 0..100 {
+            let password = policy.generate().unwrap();
+            assert_eq!(password.chars().count(), 16);
+            assert!(password.chars().any(|ch| LOWERCASE.contains(ch)));
+            assert!(password.chars().any(|ch| UPPERCASE.contains(ch)));
+            assert!(password.chars().any(|ch| DIGITS.contains(ch)));
+            assert!(password.chars().any(|ch| "-_".contains(ch)));
+            assert!(!password.chars().any(|ch| "abc0O1Il|".contains(ch)));
+        }
+    }
+
+    #[test]
+    fn entropy() {
+        let mut policy = PasswordPolicy::default();
+        policy.set_length(10);
+        policy.set_uppercase(false);
+        policy.set_symbols(String::new());
+        // 26 lowercase letters and 10 digits
+        let expected = 10.0 * 36f64.log2();
+        assert!((policy.entropy_bits().unwrap() - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn invalid_policies() {
+        let mut policy = PasswordPolicy::default();
+        policy.set_length(3);
+        policy.generate().unwrap_err();
+
+        let mut policy = PasswordPolicy::default();
+        policy.set_digits(false);
+        policy.set_lowercase(false);
+        policy.set_uppercase(false);
+        policy.set_symbols("&".into());
+        policy.set_excluded("&".into());
+        policy.generate().unwrap_err();
+    }
+
+    #[test]
+    fn wordlist_has_unique_dice_words() {
+        let words = wordlist();
+        assert_eq!(words.len(), 6usize.pow(5));
+        assert_eq!(words.iter().collect::<HashSet<_>>().len(), words.len());
+
+        // lines are numbered by dice rolls in order: 11111, 11112, ..., 66666
+        for (index, line) in WORDLIST.lines().enumerate() {
+            let rolls: String = (0..5)
+                .rev()
+                .map(|digit| char::from(b'1' + (index / 6usize.pow(digit) % 6) as u8))
+                .collect();
+            assert_eq!(line.split('\t').next(), Some(rolls.as_str()));
+        }
+    }
+
+    #[test]
+    fn generated_passphrase_follows_policy() {
+        let policy = PassphrasePolicy::new(4, ".".into(), true, true);
+        let passphrase = policy.generate().unwrap();
+        let words: Vec<&str> = passphrase.split('.').collect();
+        assert_eq!(words.len(), 4);
+        assert!(words
+            .iter()
+            .all(|word| word.starts_with(|ch: char| ch.is_ascii_uppercase())));
+        assert!(words[3].ends_with(|ch: char| ch.is_ascii_digit()));
+
+        let expected = 4.0 * 7776f64.log2() + 10f64.log2();
+        assert!((policy.entropy_bits() - expected).abs() < 1e-9);
+    }
+}
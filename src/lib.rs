@@ -1,10 +1,23 @@
 #![warn(rust_2018_idioms)]
 // #![warn(missing_debug_implementations, rust_2018_idioms, missing_docs)]
+#![allow(clippy::needless_return)]
 
+mod base32;
 pub mod cli;
 pub mod encryption;
+pub mod error;
+#[cfg(any(test, feature = "test-fixtures"))]
+pub mod fixture;
+pub mod generator;
 pub mod model;
+pub mod otp;
+pub mod recovery;
+pub mod secret;
+pub mod shamir;
+pub mod timestamp;
+
+pub use error::{Error, Result};
 
 #[cfg(test)]
+#[allow(dead_code)]
 mod bench;
-
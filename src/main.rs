@@ -16,5 +16,8 @@ fn main() {
     let cli_args = CliArgs::parse();
     log::trace!("finished parsing CLI arguments");
 
-    cli::run(cli_args).expect("password-cli error");
+    if let Err(err) = cli::run(cli_args) {
+        eprintln!("error: {}", err);
+        std::process::exit(err.exit_code());
+    }
 }
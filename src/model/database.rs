@@ -1,428 +1,3385 @@
-use std::{fs, path::Path};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{Read, Write},
+    path::Path,
+};
 
-use aes_gcm::{aead::consts::U12, Nonce};
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
 use fallible_iterator::FallibleIterator;
-use rusqlite::{Connection, OpenFlags};
+use rusqlite::{Connection, OpenFlags, OptionalExtension, Transaction};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
 
-use crate::encryption::{self, Cipher, DecryptedMessage};
+use crate::{
+    encryption::{
+        Cipher, CipherAlgorithm, CipherKey, DecryptedMessage, KdfParams, KeyFileHash, SALT_SIZE,
+    },
+    generator::PasswordPolicy,
+    recovery::{self, RecoveryKey, RecoveryShare},
+    secret::SecretString,
+    timestamp, Error, Result,
+};
 
-use super::{Entry, EntryData, EntryGroup, EntryGroupData};
+use self::blob::BlobHeader;
+use super::{
+    path::{self, ItemRef},
+    Attachment, Credentials, Entry, EntryData, EntryGroup, EntryGroupData, KeySlot, KeySlotKind,
+};
 
-pub struct Database<'a> {
+mod blob;
+mod migration;
+
+pub const VAULT_ID_SIZE: usize = 16usize;
+/// Random id of a vault, bound to each of its encrypted blobs
+pub type VaultId = [u8; VAULT_ID_SIZE];
+
+pub struct Database {
     conn: Connection,
-    cipher: &'a Cipher,
+    /// `None` for vaults not yet migrated to schema version 2, whose blobs
+    /// are encrypted without associated data.
+    vault_id: Option<VaultId>,
+    /// Key encrypting every blob of the vault. It is random and wrapped in
+    /// key slots, or derived from the credentials for legacy vaults.
+    data_key: CipherKey,
+    cipher: Cipher,
+    /// `None` for legacy vaults, which have no key slots.
+    unlocked_slot: Option<UnlockedSlot>,
+    /// Parameters used to derive the key that unlocked the vault. `None` for
+    /// legacy vaults whose key is an unsalted hash of the credentials.
+    kdf: Option<KdfParams>,
+    /// Key file mixed into the key of every password slot, for vaults
+    /// requiring one as a second factor. `None` when the vault was opened
+    /// with a key file slot.
+    key_file: Option<KeyFileHash>,
+}
+
+/// Key slot whose secret was used to open the database
+struct UnlockedSlot {
+    id: i64,
+    kind: KeySlotKind,
+    cipher: Cipher,
 }
 
-impl<'a> Database<'a> {
+impl Database {
     const ROOT_GROUP_ID: i64 = 1;
-    const CHALLENGE: &[u8; 32] = &[
+    const METADATA_ROW_ID: i64 = 1;
+    const STRUCTURE_INDEX_ROW_ID: i64 = 1;
+    const CHALLENGE: &'static [u8; 32] = &[
         71, 241, 152, 110, 194, 42, 171, 124, 37, 122, 211, 128, 57, 254, 234, 253, 82, 237, 131,
         16, 141, 243, 50, 140, 6, 250, 169, 73, 249, 69, 19, 174,
     ];
+    /// (table, column) pairs holding blobs encrypted with the vault data key
+    const ENCRYPTED_COLUMNS: &'static [(&'static str, &'static str)] = &[
+        ("Metadata", "challenge"),
+        ("EntryGroup", "data"),
+        ("Entry", "data"),
+        ("PasswordPolicy", "data"),
+        ("StructureIndex", "data"),
+        ("Attachment", "data"),
+        ("AttachmentChunk", "data"),
+    ];
+    /// Size of the chunks attachments are encrypted in
+    const ATTACHMENT_CHUNK_SIZE: usize = 64 * 1024;
+    /// Same as in the creation script, used to upgrade legacy vaults
+    const KEY_SLOT_TABLE: &'static str = "
+CREATE TABLE KeySlot (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    kind INTEGER NOT NULL,
+    label TEXT NOT NULL,
+    kdf_salt BLOB NOT NULL,
+    kdf_memory INTEGER NOT NULL,
+    kdf_iterations INTEGER NOT NULL,
+    kdf_parallelism INTEGER NOT NULL,
+    wrapped_key BLOB NOT NULL,
+    wrapped_key_bound INTEGER NOT NULL DEFAULT 0
+);";
+    pub const DEFAULT_ROOT_GROUP_NAME: &'static str = "root";
+    pub const MASTER_PASSWORD_LABEL: &'static str = "master password";
+    pub const RECOVERY_KEY_LABEL: &'static str = "recovery key";
+    pub const MAX_ATTACHMENT_SIZE: u64 = 16 * 1024 * 1024;
+    /// Limit on the total size of an entry's attachments
+    pub const MAX_ENTRY_ATTACHMENTS_SIZE: u64 = 64 * 1024 * 1024;
 
-    pub fn create(path: impl AsRef<Path>, cipher: &'a Cipher) -> Result<Self, ()> {
+    /// Create an empty vault, with only a root group of the given name, and
+    /// a password key slot for the credentials. Nothing is left at `path` if
+    /// the creation fails.
+    pub fn create(
+        path: impl AsRef<Path>,
+        credentials: &Credentials,
+        kdf: KdfParams,
+        root_group_name: &str,
+    ) -> Result<Self> {
         if path.as_ref().exists() {
-            return Err(());
+            return Err(Error::VaultAlreadyExists(path.as_ref().to_path_buf()));
         }
 
-        let conn = Connection::open(path.as_ref()).map_err(|_| ())?;
-        let db = Database { conn, cipher };
+        // deriving the slot key validates the kdf parameters
+        let slot_key = derive_slot_key(
+            &kdf,
+            KeySlotKind::Password,
+            credentials.password().as_bytes(),
+            credentials.key_file(),
+        )?;
+        let slot_cipher = Cipher::new(&slot_key);
+
+        let conn = Connection::open(path.as_ref())?;
+        return Self::initialize(conn, credentials, kdf, slot_cipher, root_group_name).map_err(
+            |err| {
+                let _ = std::fs::remove_file(path.as_ref());
+                return err;
+            },
+        );
+    }
+
+    /// Write the schema and the initial rows of a vault created by
+    /// [`Database::create`] to the empty database `conn`.
+    fn initialize(
+        conn: Connection,
+        credentials: &Credentials,
+        kdf: KdfParams,
+        slot_cipher: Cipher,
+        root_group_name: &str,
+    ) -> Result<Self> {
+        let data_key = CipherKey::generate();
+        let mut vault_id = VaultId::default();
+        OsRng.fill_bytes(&mut vault_id);
+        let mut db = Database {
+            conn,
+            vault_id: Some(vault_id),
+            cipher: Cipher::new(&data_key),
+            data_key,
+            unlocked_slot: None,
+            kdf: None,
+            key_file: credentials.key_file().cloned(),
+        };
         let challenge = Self::CHALLENGE;
-        db.init_test_tables();
+        db.conn.execute_batch(migration::SCHEMA)?;
+        let tx = db.conn.unchecked_transaction()?;
+        migration::set_schema_version(&tx, migration::SCHEMA_VERSION)?;
+        db.insert_root_entry_group(&EntryGroupData::new(root_group_name.into()))?;
 
-        db.conn
-            .execute(
-                "INSERT INTO Metadata(challenge) VALUES (?1);",
-                [encrypt_blob(challenge.as_slice(), db.cipher)],
-            )
-            .unwrap();
+        tx.execute(
+            "
+INSERT INTO Metadata(rowid, challenge, vault_id, requires_key_file)
+VALUES (?1, ?2, ?3, ?4);",
+            rusqlite::params![
+                Self::METADATA_ROW_ID,
+                encrypt_blob(
+                    challenge.as_slice(),
+                    &db.cipher,
+                    &db.associated_data("Metadata", Self::METADATA_ROW_ID),
+                )?,
+                vault_id.as_slice(),
+                db.key_file.is_some(),
+            ],
+        )?;
+
+        let slot_id = insert_wrapped_key_slot(
+            &tx,
+            KeySlotKind::Password,
+            Self::MASTER_PASSWORD_LABEL,
+            &kdf,
+            &slot_cipher,
+            (&db.data_key, db.vault_id.as_ref()),
+        )?;
+        tx.commit()?;
+        db.unlocked_slot = Some(UnlockedSlot {
+            id: slot_id,
+            kind: KeySlotKind::Password,
+            cipher: slot_cipher,
+        });
+        db.kdf = Some(kdf);
 
         return Ok(db);
     }
 
-    pub fn open(path: impl AsRef<Path>, cipher: &'a Cipher) -> Result<Self, rusqlite::Error> {
-        // let mut is_new = false;
-        let conn = Connection::open_with_flags(
-            path.as_ref(),
-            OpenFlags::default() & !OpenFlags::SQLITE_OPEN_CREATE,
-        )?;
-        // .or_else(|err| {
-        //     if let rusqlite::Error::SqliteFailure(
-        //         rusqlite::ffi::Error {
-        //             code: rusqlite::ffi::ErrorCode::CannotOpen,
-        //             ..
-        //         },
-        //         ..,
-        //     ) = err
-        //     {
-        //         is_new = true;
-        //         Connection::open(path.as_ref())
-        //     } else {
-        //         Err(err)
-        //     }
-        // })?;
-
-        let db = Database { conn, cipher };
-        let challenge = Self::CHALLENGE;
-        // if is_new {
-        //     db.init_test_tables();
-        //
-        //     db.conn
-        //         .execute(
-        //             "INSERT INTO Metadata(challenge) VALUES (?1);",
-        //             [encrypt_blob(challenge.as_slice(), db.cipher)],
-        //         )
-        //         .unwrap();
-        // } else {
-        // verify that the given cipher can decrypt this db
-        let encrypted_challenge: Vec<u8> =
-            db.conn
-                .query_row("SELECT challenge FROM Metadata", [], |row| row.get(0))?;
+    /// Open the database with the password of one of its key slots, or with
+    /// the credentials' derived key for legacy vaults. The credentials must
+    /// include the key file of vaults requiring one. Vaults with an older
+    /// schema are migrated.
+    pub fn open(path: impl AsRef<Path>, credentials: &Credentials) -> Result<Self> {
+        let conn = Self::connect(path)?;
 
-        let decrypted_challenge = decrypt_blob(encrypted_challenge.as_slice(), db.cipher).unwrap();
+        match (Self::read_requires_key_file(&conn)?, credentials.key_file()) {
+            (true, None) => return Err(Error::KeyFileRequired),
+            (false, Some(_)) => {
+                return Err(Error::InvalidInput(
+                    "this vault does not use a key file".into(),
+                ))
+            }
+            _ => {}
+        }
 
-        assert_eq!(decrypted_challenge.as_slice(), challenge.as_slice());
-        // }
+        let mut db = if Self::has_key_slots(&conn)? {
+            Self::unlock(
+                conn,
+                KeySlotKind::Password,
+                credentials.password().as_bytes(),
+                credentials.key_file(),
+            )?
+        } else {
+            Self::open_legacy(conn, credentials)?
+        };
+        migration::migrate(&mut db)?;
+        db.vault_id = read_vault_id(&db.conn)?;
+        db.bind_unlocked_slot();
 
         return Ok(db);
     }
 
-    pub fn root_group_id(&self) -> i64 {
-        return Self::ROOT_GROUP_ID;
-        //         return self
-        //             .conn
-        //             .query_row(
-        //                 "
-        // SELECT id
-        // FROM EntryGroup e
-        // WHERE e.id NOT IN (
-        //     SELECT child_id FROM EntryGroupParent
-        // );",
-        //                 [],
-        //                 |row| row.get(0),
-        //             )
-        //             .expect("there should be a group with no parent");
+    /// Open the database with the content of a key file registered in one of
+    /// its key slots. Vaults with an older schema are migrated.
+    pub fn open_with_key_file(path: impl AsRef<Path>, key_file: &[u8]) -> Result<Self> {
+        let conn = Self::connect(path)?;
+
+        let mut db = Self::unlock(conn, KeySlotKind::KeyFile, key_file, None)?;
+        migration::migrate(&mut db)?;
+        db.vault_id = read_vault_id(&db.conn)?;
+        db.bind_unlocked_slot();
+
+        return Ok(db);
     }
 
-    pub fn entry(&self, row_id: i64) -> Result<Entry, ()> {
-        let entry_data = self.decrypt_blob("Entry", "data", row_id).unwrap();
-        let deserialized = ciborium::from_reader(entry_data.as_slice()).unwrap();
-        return Ok(Entry::new(row_id, deserialized));
+    /// Open the database with its recovery key, e.g. to reset a lost master
+    /// password with [`Database::reset_master_password`]. Vaults with an
+    /// older schema are migrated.
+    pub fn open_with_recovery_key(
+        path: impl AsRef<Path>,
+        recovery_key: &RecoveryKey,
+    ) -> Result<Self> {
+        let conn = Self::connect(path)?;
+
+        let mut db = Self::unlock(conn, KeySlotKind::Recovery, recovery_key.as_bytes(), None)?;
+        migration::migrate(&mut db)?;
+        db.vault_id = read_vault_id(&db.conn)?;
+        db.bind_unlocked_slot();
+
+        return Ok(db);
     }
 
-    pub fn insert_entry(&self, group_id: i64, entry: &EntryData) -> Result<i64, ()> {
-        let mut serialized: Vec<u8> = vec![];
-        ciborium::into_writer(entry, &mut serialized).unwrap();
-        let mut stmt = self
-            .conn
-            .prepare("INSERT INTO Entry(group_id, data) VALUES (?, ?);")
-            .unwrap();
-        stmt.execute(rusqlite::params![
-            group_id,
-            encrypt_blob(serialized.as_slice(), self.cipher),
-        ])
-        .unwrap();
+    /// Open the database with at least a threshold of the recovery shares
+    /// made by [`Database::add_recovery_shares`]. Vaults with an older schema
+    /// are migrated.
+    pub fn open_with_recovery_shares(
+        path: impl AsRef<Path>,
+        shares: Vec<RecoveryShare>,
+    ) -> Result<Self> {
+        let conn = Self::connect(path)?;
+        let secret = recovery::combine_shares(shares)?;
 
-        //         self.conn
-        //             .execute(
-        //                 "
-        // INSERT INTO Entry(group_id, data) VALUES (?, ?);
-        // ",
-        //                 rusqlite::params![group_id, encrypt_blob(serialized.as_slice(), self.cipher),],
-        //             )
-        //             .unwrap(); //TODO: error handling
+        let mut db = Self::unlock(conn, KeySlotKind::RecoveryShares, secret.as_ref(), None)?;
+        migration::migrate(&mut db)?;
+        db.vault_id = read_vault_id(&db.conn)?;
+        db.bind_unlocked_slot();
 
-        return Ok(self.conn.last_insert_rowid());
+        return Ok(db);
     }
 
-    pub fn entries(
-        &self,
-        name_filter: Option<&str>,
-        parent_group_id: Option<i64>,
-    ) -> Result<Vec<Entry>, ()> {
-        let mut stmt;
-        let rows = if let Some(parent_id) = parent_group_id {
-            stmt = self
-                .conn
-                .prepare(
-                    "
-SELECT id, data FROM Entry
-WHERE group_id = ?1;
-",
-                )
-                .unwrap();
-            stmt.query([parent_id]).unwrap()
+    fn connect(path: impl AsRef<Path>) -> Result<Connection> {
+        if !path.as_ref().exists() {
+            return Err(Error::VaultNotFound(path.as_ref().to_path_buf()));
+        }
+
+        let conn = Connection::open_with_flags(
+            path.as_ref(),
+            OpenFlags::default() & !OpenFlags::SQLITE_OPEN_CREATE,
+        )?;
+        migration::check_schema_version(&conn)?;
+
+        return Ok(conn);
+    }
+
+    /// Try each key slot of the given kind until one of them can be
+    /// unwrapped with `secret`, and `key_file` for password slots of vaults
+    /// requiring one, into a data key passing the challenge.
+    fn unlock(
+        mut conn: Connection,
+        kind: KeySlotKind,
+        secret: &[u8],
+        key_file: Option<&KeyFileHash>,
+    ) -> Result<Self> {
+        // keys wrapped before schema version 8 have no associated data
+        let bound_column = if Self::has_wrapped_key_bound_column(&conn)? {
+            "wrapped_key_bound"
         } else {
-            stmt = self.conn.prepare("SELECT id, data FROM Entry").unwrap();
-            stmt.query([]).unwrap()
+            "0"
         };
-        let rows = rows.map(|row| Ok((row.get(0)?, row.get(1)?)));
-        // stmt.query_map([parent_id], |row| Ok((row.get(0)?, row.get(1)?))).unwrap()
+        let select = format!(
+            "
+SELECT id, kdf_salt, kdf_memory, kdf_iterations, kdf_parallelism, wrapped_key, {}
+FROM KeySlot
+WHERE kind = ?1
+ORDER BY id;",
+            bound_column
+        );
+        let slots: Vec<(i64, KdfParams, Vec<u8>, bool)> = conn
+            .prepare(select.as_str())?
+            .query_map([kind.to_i64()], |row| {
+                let salt: Vec<u8> = row.get(1)?;
+                Ok((
+                    row.get(0)?,
+                    salt,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                    row.get(6)?,
+                ))
+            })?
+            .map(|row| {
+                let (slot_id, salt, memory_kib, iterations, parallelism, wrapped_key, bound) = row?;
+                let salt: [u8; SALT_SIZE] =
+                    salt.as_slice().try_into().map_err(|_| Error::CorruptBlob)?;
+                let kdf = KdfParams::new(salt, memory_kib, iterations, parallelism);
+                Ok((slot_id, kdf, wrapped_key, bound))
+            })
+            .collect::<Result<_>>()?;
+        let vault_id = read_vault_id(&conn)?;
 
-        return Ok(rows
-            .filter_map(|(entry_id, blob_buf)| {
-                // if let Ok((entry_id, blob_buf)) = mapped_row {
-                let decrypted = decrypt_blob(Vec::as_slice(&blob_buf), self.cipher).unwrap();
-                let entry_data: EntryData = ciborium::from_reader(decrypted.as_slice()).unwrap();
+        let mut last_error = Error::WrongCredentials;
+        for (slot_id, kdf, wrapped_key, bound) in slots {
+            let slot_key = derive_slot_key(&kdf, kind, secret, key_file)?;
+            let slot_cipher = Cipher::new(&slot_key);
+            let associated_data = if bound {
+                associated_data(vault_id.as_ref(), "KeySlot", slot_id)
+            } else {
+                vec![]
+            };
 
-                if let Some(name_filter) = name_filter {
-                    if !entry_data.name().contains(name_filter) {
-                        return Ok(None);
-                    }
+            if let Ok(data_key) = unwrap_data_key(
+                wrapped_key.as_slice(),
+                &slot_cipher,
+                slot_id,
+                &associated_data,
+            ) {
+                let data_key: [u8; 32] = data_key
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| Error::CorruptBlob)?;
+                let data_key = CipherKey::new(data_key);
+                let db = Database {
+                    vault_id,
+                    cipher: Cipher::with_algorithm(&data_key, read_cipher_algorithm(&conn)?),
+                    conn,
+                    data_key,
+                    unlocked_slot: Some(UnlockedSlot {
+                        id: slot_id,
+                        kind,
+                        cipher: slot_cipher,
+                    }),
+                    kdf: Some(kdf),
+                    key_file: key_file.cloned(),
+                };
+                if let Err(err) = db.verify_challenge() {
+                    log::debug!("key slot {} unwraps a key failing the challenge", slot_id);
+                    last_error = err;
+                    conn = db.conn;
+                    continue;
                 }
-                return Ok(Some(Entry::new(entry_id, entry_data)));
-                // } else {
-                //     return None;
-                // }
-            })
-            .collect()
-            .unwrap());
+
+                return Ok(db);
+            }
+        }
+
+        log::debug!("no {} key slot could be unlocked", kind.name());
+        return Err(last_error);
     }
 
-    pub fn groups(&self, parent_group_id: Option<i64>) -> Result<Vec<EntryGroup>, ()> {
-        //         let (mut stmt, params) = if let Some(parent_id) = parent_group_id {
-        //             (
-        //                 self.conn
-        //                     .prepare("
-        // SELECT id, data FROM EntryGroup
-        // WHERE id IN (
-        //     SELECT child_id FROM EntryGroupParent
-        //     WHERE parent_id = ?1
-        // );")
-        //                     .unwrap(),
-        //                 vec![parent_id],
-        //             )
-        //             // stmt.query([]).unwrap()
-        //             // todo!()
-        //         } else {
-        //             (
-        //                 self.conn
-        //                     .prepare("SELECT id, data FROM EntryGroup")
-        //                     .unwrap(),
-        //                 vec![],
-        //             )
-        //             // stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap()
-        //         };
-        //         let rows = stmt.query(params.as_slice()).unwrap();
-        let mut stmt;
-        let rows = if let Some(parent_id) = parent_group_id {
-            stmt = self
-                .conn
-                .prepare(
-                    "
-SELECT id, data FROM EntryGroup
-WHERE id IN (
-    SELECT child_id FROM EntryGroupParent
-    WHERE parent_id = ?1
-);",
-                )
-                .unwrap();
-            stmt.query([parent_id])
-        } else {
-            stmt = self
-                .conn
-                .prepare("SELECT id, data FROM EntryGroup;")
-                .unwrap();
-            stmt.query([])
+    fn open_legacy(conn: Connection, credentials: &Credentials) -> Result<Self> {
+        let kdf = Self::read_kdf_params(&conn)?;
+        let data_key = match &kdf {
+            Some(kdf) => credentials.derive_key(kdf)?,
+            None => credentials.legacy_key(),
         };
+        log::warn!("opening legacy vault without key slots");
 
-        let groups = rows
-            .unwrap()
-            .map(|row| {
-                let group_id = row.get(0).unwrap();
-                let data_blob = row.get(1).unwrap();
+        let db = Database {
+            vault_id: read_vault_id(&conn)?,
+            cipher: Cipher::with_algorithm(&data_key, read_cipher_algorithm(&conn)?),
+            conn,
+            data_key,
+            unlocked_slot: None,
+            kdf,
+            key_file: None,
+        };
+        db.verify_challenge()?;
 
-                let decrypted = decrypt_blob(Vec::as_slice(&data_blob), self.cipher).unwrap();
-                let group_data: EntryGroupData =
-                    ciborium::from_reader(decrypted.as_slice()).unwrap();
+        return Ok(db);
+    }
 
-                return Ok(EntryGroup::new(group_id, group_data));
-            })
-            .collect()
-            .unwrap();
+    /// Rewrap the data key of the unlocked slot if it was wrapped before
+    /// schema version 8, binding it to the vault and slot, see
+    /// [`wrap_data_key`]. Other such slots are rewrapped when they unlock the
+    /// vault. A failure, e.g. on a read-only vault, leaves the slot as it was.
+    fn bind_unlocked_slot(&self) {
+        let Some(slot) = &self.unlocked_slot else {
+            return;
+        };
+        let result = self
+            .conn
+            .query_row(
+                "SELECT wrapped_key_bound FROM KeySlot WHERE id = ?1;",
+                [slot.id],
+                |row| row.get::<_, bool>(0),
+            )
+            .map_err(Error::from)
+            .and_then(|bound| {
+                if bound {
+                    return Ok(());
+                }
+                let wrapped_key = wrap_data_key(
+                    &self.data_key,
+                    &slot.cipher,
+                    slot.id,
+                    self.vault_id.as_ref(),
+                )?;
+                self.conn.execute(
+                    "UPDATE KeySlot SET wrapped_key = ?1, wrapped_key_bound = 1 WHERE id = ?2;",
+                    rusqlite::params![wrapped_key, slot.id],
+                )?;
+                return Ok(());
+            });
 
-        return Ok(groups);
+        if let Err(err) = result {
+            log::warn!(
+                "could not rewrap the data key of key slot {}: {}",
+                slot.id,
+                err
+            );
+        }
     }
 
-    // TODO: use Option<i64> for parent id to not make querying DB beforehand
-    //   necessary, but that would mean not being able to create a root-level
-    //   group with this method.
-    pub fn insert_entry_group(
-        &mut self,
-        parent_group_id: i64,
-        group_data: &EntryGroupData,
-    ) -> Result<i64, ()> {
-        let mut serialized: Vec<u8> = vec![];
-        let tx = self.conn.transaction().unwrap();
+    /// Verify that the data key can decrypt this database
+    fn verify_challenge(&self) -> Result<()> {
+        let challenge = Self::CHALLENGE;
+        let (row_id, encrypted_challenge): (i64, Vec<u8>) =
+            self.conn
+                .query_row("SELECT rowid, challenge FROM Metadata", [], |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })?;
 
-        ciborium::into_writer(group_data, &mut serialized).unwrap();
-        tx.execute(
-            "INSERT INTO EntryGroup(data) VALUES (?1);",
-            rusqlite::params![encrypt_blob(serialized.as_slice(), self.cipher),],
+        let decrypted_challenge = decrypt_blob(
+            encrypted_challenge.as_slice(),
+            &self.cipher,
+            &self.associated_data("Metadata", row_id),
         )
-        .unwrap();
-        let entry_group_id = tx.last_insert_rowid();
+        .map_err(|_| Error::WrongCredentials)?;
 
-        tx.execute(
-            "INSERT INTO EntryGroupParent(parent_id, child_id) VALUES (?1, ?2);",
-            rusqlite::params![parent_group_id, entry_group_id,],
-        )
-        .unwrap();
+        if decrypted_challenge.as_slice() != challenge.as_slice() {
+            return Err(Error::WrongCredentials);
+        }
 
-        tx.commit().unwrap();
+        return Ok(());
+    }
 
-        return Ok(entry_group_id);
+    /// Whether this vault's data is encrypted directly with a key derived
+    /// from the credentials instead of a data key stored in key slots, see
+    /// [`Database::upgrade`].
+    pub fn is_legacy(&self) -> bool {
+        return self.unlocked_slot.is_none();
     }
 
-    // TODO: refactor copied code from Database::insert_entry_group
-    pub fn insert_root_entry_group(&self, group_data: &EntryGroupData) -> Result<i64, ()> {
-        let mut serialized: Vec<u8> = vec![];
-        ciborium::into_writer(group_data, &mut serialized).unwrap();
-        self.conn
-            .execute(
-                "INSERT INTO EntryGroup(id, data) VALUES (?1, ?2);", // TODO: use fixed id =1 or-1 or
-                // something
-                rusqlite::params![
-                    Self::ROOT_GROUP_ID,
-                    encrypt_blob(serialized.as_slice(), self.cipher),
-                ],
-            )
-            .unwrap();
+    pub fn kdf(&self) -> Option<&KdfParams> {
+        self.kdf.as_ref()
+    }
 
-        return Ok(self.conn.last_insert_rowid());
+    /// Id of the key slot used to open the database
+    pub fn unlocked_slot_id(&self) -> Option<i64> {
+        return self.unlocked_slot.as_ref().map(|slot| slot.id);
     }
 
-    pub fn init_test_tables(&self) {
-        let creation_script_path = std::env::var("DB_CREATION_SCRIPT")
-            .expect("missing DB_CREATION_SCRIPT environment variable");
+    /// Move a legacy vault to a random data key, stored in a new password key
+    /// slot. Every blob is re-encrypted in a single transaction.
+    pub fn upgrade(&mut self, credentials: &Credentials, kdf: KdfParams) -> Result<()> {
+        if !self.is_legacy() {
+            return Err(Error::InvalidInput(
+                "the vault already uses key slots".into(),
+            ));
+        }
 
-        self.conn
-            .execute_batch(
-                fs::read_to_string(creation_script_path)
-                    .expect("could not read DB creation script")
-                    .as_str(),
-            )
-            .unwrap();
-        log::trace!("created tables");
+        let new_data_key = CipherKey::generate();
+        let new_cipher = Cipher::with_algorithm(&new_data_key, self.cipher.algorithm());
+        let tx = self.conn.transaction()?;
 
-        let group_id = self
-            .insert_root_entry_group(&EntryGroupData::new("root group name".into()))
-            .unwrap();
+        reencrypt_blobs(
+            &tx,
+            Self::ENCRYPTED_COLUMNS,
+            (&self.cipher, self.vault_id.as_ref()),
+            (&new_cipher, self.vault_id.as_ref()),
+        )?;
+        tx.execute_batch(Self::KEY_SLOT_TABLE)?;
+        if Self::has_kdf_columns(&tx)? {
+            tx.execute(
+                "
+UPDATE Metadata
+SET kdf_salt = NULL, kdf_memory = NULL, kdf_iterations = NULL, kdf_parallelism = NULL;",
+                [],
+            )?;
+        }
+        let (slot_id, slot_cipher) = insert_key_slot(
+            &tx,
+            KeySlotKind::Password,
+            Self::MASTER_PASSWORD_LABEL,
+            credentials.password().as_bytes(),
+            &kdf,
+            (&new_data_key, self.vault_id.as_ref()),
+            None,
+        )?;
+        tx.commit()?;
 
-        let entry_one_blob_id = self
-            .insert_entry(
-                group_id,
-                &EntryData::new("abcdef".into(), "fghijkl".into(), "password".into()),
-            )
-            .unwrap();
+        self.data_key = new_data_key;
+        self.cipher = new_cipher;
+        self.unlocked_slot = Some(UnlockedSlot {
+            id: slot_id,
+            kind: KeySlotKind::Password,
+            cipher: slot_cipher,
+        });
+        self.kdf = Some(kdf);
 
-        let inserted_entry_one_blob = self.entry(entry_one_blob_id).unwrap();
-        log::trace!(
-            "inserted encrypted entry with one data blob: {:?}",
-            inserted_entry_one_blob
-        );
+        return Ok(());
+    }
 
-        self.insert_entry(
-            group_id,
-            &EntryData::new("entry name".into(), "u".into(), "password123".into()),
-        )
-        .unwrap();
+    /// Rotate the data key: every blob is re-encrypted with `new_data_key` in
+    /// a single transaction, so the vault is left untouched if anything fails
+    /// partway. Only the key slot used to open the database can be wrapped
+    /// again: the other slots are removed in the same transaction if
+    /// `remove_other_slots`, and the vault is refused otherwise. Returns the
+    /// removed slots, which must be issued again.
+    pub fn rekey(
+        &mut self,
+        new_data_key: CipherKey,
+        remove_other_slots: bool,
+    ) -> Result<Vec<KeySlot>> {
+        let Some(slot) = &self.unlocked_slot else {
+            return Err(Error::InvalidInput(
+                "legacy vaults must be upgraded before rotating their key".into(),
+            ));
+        };
+        let other_slots: Vec<KeySlot> = self
+            .key_slots()?
+            .into_iter()
+            .filter(|other| other.id() != slot.id)
+            .collect();
+        if !other_slots.is_empty() && !remove_other_slots {
+            let names: Vec<String> = other_slots
+                .iter()
+                .map(|other| format!("{} ({})", other.id(), other.kind().name()))
+                .collect();
+            return Err(Error::InvalidInput(format!(
+                "key slots {} cannot be rewrapped without their secrets, remove them to rotate the data key",
+                names.join(", ")
+            )));
+        }
+        let new_cipher = Cipher::with_algorithm(&new_data_key, self.cipher.algorithm());
+        let tx = self.conn.transaction()?;
 
-        // bench::bench(
-        //     || {
-        //         self.entry_with_read(entry_one_blob_id);
-        //     },
-        //     "db.entry_with_read()",
-        // );
-        //
-        // bench::bench(
-        //     || {
-        //         self.entry(entry_one_blob_id);
-        //     },
-        //     "db.entry()",
-        // );
-        //
-        // let insert_counts = [1, 10, 50];
-        //
-        // let entry = Entry::new("a".into(), "b".into(), "c".into());
-        // bench::bench_with_counts(
-        //     || {
-        //         self.insert_entry(group_id, &entry).unwrap();
-        //     },
-        //     "db.insert_entry() (no alloc)",
-        //     &insert_counts,
-        // );
-        // self.conn
-        //     .execute("DELETE FROM Entry WHERE 1 = 1;", [])
-        //     .unwrap();
-        //
-        // bench::bench_with_counts(
-        //     || {
-        //         let entry = Entry::new("a".into(), "b".into(), "c".into());
-        //         self.insert_entry(group_id, &entry).unwrap();
-        //     },
-        //     "db.insert_entry() (alloc)",
-        //     &insert_counts,
-        // );
-        // self.conn
-        //     .execute("DELETE FROM Entry WHERE 1 = 1;", [])
-        //     .unwrap();
+        reencrypt_blobs(
+            &tx,
+            Self::ENCRYPTED_COLUMNS,
+            (&self.cipher, self.vault_id.as_ref()),
+            (&new_cipher, self.vault_id.as_ref()),
+        )?;
+        tx.execute(
+            "UPDATE KeySlot SET wrapped_key = ?1, wrapped_key_bound = 1 WHERE id = ?2;",
+            rusqlite::params![
+                wrap_data_key(&new_data_key, &slot.cipher, slot.id, self.vault_id.as_ref())?,
+                slot.id
+            ],
+        )?;
+        tx.execute("DELETE FROM KeySlot WHERE id != ?1;", [slot.id])?;
+        tx.commit()?;
+
+        self.data_key = new_data_key;
+        self.cipher = new_cipher;
+
+        return Ok(other_slots);
     }
 
-    fn decrypt_blob(&self, table: &str, column: &str, row_id: i64) -> Result<DecryptedMessage, ()> {
-        let query = format!("SELECT \"{}\" FROM \"{}\" WHERE id = ?", column, table);
-        let blob_buf: Vec<u8> = self
+    /// AEAD algorithm encrypting the vault's data
+    pub fn cipher_algorithm(&self) -> CipherAlgorithm {
+        self.cipher.algorithm()
+    }
+
+    /// Re-encrypt every blob with `algorithm`, keeping the data key, in a
+    /// single transaction. Key slots keep wrapping the data key with
+    /// AES-256-GCM, as they cannot be rewrapped without their secrets.
+    pub fn set_cipher_algorithm(&mut self, algorithm: CipherAlgorithm) -> Result<()> {
+        if self.cipher.algorithm() == algorithm {
+            return Ok(());
+        }
+        if self.is_legacy() {
+            return Err(Error::InvalidInput(
+                "legacy vaults must be upgraded before changing their cipher".into(),
+            ));
+        }
+        let new_cipher = Cipher::with_algorithm(&self.data_key, algorithm);
+        let tx = self.conn.transaction()?;
+
+        reencrypt_blobs(
+            &tx,
+            Self::ENCRYPTED_COLUMNS,
+            (&self.cipher, self.vault_id.as_ref()),
+            (&new_cipher, self.vault_id.as_ref()),
+        )?;
+        tx.execute("UPDATE Metadata SET cipher = ?1;", [algorithm.id()])?;
+        tx.commit()?;
+
+        self.cipher = new_cipher;
+
+        return Ok(());
+    }
+
+    /// Replace the password of the key slot used to open the database. Legacy
+    /// vaults are upgraded with the new password.
+    pub fn change_password(&mut self, new_credentials: &Credentials, kdf: KdfParams) -> Result<()> {
+        let Some(slot) = &mut self.unlocked_slot else {
+            return self.upgrade(new_credentials, kdf);
+        };
+        if slot.kind != KeySlotKind::Password {
+            return Err(Error::InvalidInput(
+                "the database was not opened with a password".into(),
+            ));
+        }
+
+        let slot_key = derive_slot_key(
+            &kdf,
+            KeySlotKind::Password,
+            new_credentials.password().as_bytes(),
+            self.key_file.as_ref(),
+        )?;
+        let slot_cipher = Cipher::new(&slot_key);
+        self.conn.execute(
+            "
+UPDATE KeySlot
+SET kdf_salt = ?1, kdf_memory = ?2, kdf_iterations = ?3, kdf_parallelism = ?4, wrapped_key = ?5,
+    wrapped_key_bound = 1
+WHERE id = ?6;",
+            rusqlite::params![
+                kdf.salt().as_slice(),
+                kdf.memory_kib(),
+                kdf.iterations(),
+                kdf.parallelism(),
+                wrap_data_key(
+                    &self.data_key,
+                    &slot_cipher,
+                    slot.id,
+                    self.vault_id.as_ref()
+                )?,
+                slot.id,
+            ],
+        )?;
+
+        slot.cipher = slot_cipher;
+        self.kdf = Some(kdf);
+
+        return Ok(());
+    }
+
+    pub fn key_slots(&self) -> Result<Vec<KeySlot>> {
+        let mut stmt = self
             .conn
-            .query_row(query.as_str(), rusqlite::params![row_id], |row| row.get(0))
-            .unwrap();
+            .prepare("SELECT id, kind, label FROM KeySlot ORDER BY id;")?;
+        let slots = stmt
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get::<_, String>(2)?))
+            })?
+            .map(|row| {
+                let (slot_id, kind, label) = row?;
+                let kind = KeySlotKind::from_i64(kind).ok_or(Error::CorruptBlob)?;
+                Ok(KeySlot::new(slot_id, kind, label.into()))
+            })
+            .collect::<Result<_>>()?;
 
-        return decrypt_blob(blob_buf.as_slice(), self.cipher);
-        // let blob = self
-        //     .conn
-        //     .blob_open(DatabaseName::Main, table, column, row_id, true)
-        //     .unwrap();
-        // return decrypt_blob(&blob, self.cipher);
+        return Ok(slots);
     }
-}
 
-/// Encrypt the data and return a vector of bytes containing the nonce
-/// concatenated with the encrypted data.
-///
-/// TODO: error handling
-fn encrypt_blob(data: &[u8], cipher: &Cipher) -> Vec<u8> {
-    let msg = cipher.encrypt(data).unwrap();
-    return msg
-        .nonce()
-        .iter()
-        .chain(msg.ciphertext().iter())
-        .copied()
-        .collect();
-}
+    /// Add a key slot unlocking the vault with `secret`, without touching
+    /// any entry data.
+    pub fn add_key_slot(
+        &self,
+        kind: KeySlotKind,
+        label: &str,
+        secret: &[u8],
+        kdf: &KdfParams,
+    ) -> Result<i64> {
+        if self.is_legacy() {
+            return Err(Error::InvalidInput(
+                "legacy vaults must be upgraded before adding key slots".into(),
+            ));
+        }
+        // password slots mix in the key file, unknown when opened with a
+        // key file slot
+        if kind == KeySlotKind::Password && self.key_file.is_none() && self.requires_key_file()? {
+            return Err(Error::KeyFileRequired);
+        }
 
-/// TODO: error handling
-fn decrypt_blob(blob_buf: &[u8], cipher: &Cipher) -> Result<Vec<u8>, ()> {
-    let (nonce_buf, payload_buf) = blob_buf.split_at(encryption::NONCE_SIZE);
-    let nonce_buf: &[u8; encryption::NONCE_SIZE] = nonce_buf.try_into().unwrap();
-    let nonce: &Nonce<U12> = nonce_buf.into();
+        let (slot_id, _) = insert_key_slot(
+            &self.conn,
+            kind,
+            label,
+            secret,
+            kdf,
+            (&self.data_key, self.vault_id.as_ref()),
+            self.key_file.as_ref(),
+        )?;
 
-    let decrypted_data = cipher.decrypt(payload_buf.as_ref(), nonce).unwrap();
+        return Ok(slot_id);
+    }
 
-    return Ok(decrypted_data);
-}
+    /// Generate a recovery key and store it in a new key slot, revoking the
+    /// previous recovery key if any.
+    pub fn add_recovery_key(&self, kdf: &KdfParams) -> Result<RecoveryKey> {
+        if self.is_legacy() {
+            return Err(Error::InvalidInput(
+                "legacy vaults must be upgraded before adding a recovery key".into(),
+            ));
+        }
 
-#[cfg(test)]
-mod test {
-    use crate::{encryption::Cipher, model::database::{encrypt_blob, decrypt_blob}};
+        let recovery_key = RecoveryKey::generate();
+        self.replace_key_slots(
+            KeySlotKind::Recovery,
+            Self::RECOVERY_KEY_LABEL,
+            recovery_key.as_bytes(),
+            kdf,
+        )?;
 
-    #[test]
-    fn encrypt_then_decrypt_blob() {
-        let blob_data= b"some data";
-        let cipher_secret = b"secret key";
-        let test_cipher = Cipher::new(&cipher_secret.as_slice().into());
-        let encrypted = encrypt_blob(blob_data, &test_cipher);
-        let decrypted = decrypt_blob(encrypted.as_slice(), &test_cipher).unwrap();
+        return Ok(recovery_key);
+    }
 
-        assert_eq!(decrypted.as_slice(), blob_data);
+    /// Generate a secret split into `count` recovery shares, any `threshold`
+    /// of which unlock the new key slot, revoking the previous shares if any.
+    pub fn add_recovery_shares(
+        &self,
+        kdf: &KdfParams,
+        threshold: u8,
+        count: u8,
+    ) -> Result<Vec<RecoveryShare>> {
+        if self.is_legacy() {
+            return Err(Error::InvalidInput(
+                "legacy vaults must be upgraded before adding recovery shares".into(),
+            ));
+        }
+
+        let secret = CipherKey::generate();
+        let shares = recovery::split_secret(&secret, threshold, count)?;
+        let label = format!("{} of {} recovery shares", threshold, count);
+        self.replace_key_slots(KeySlotKind::RecoveryShares, &label, secret.as_ref(), kdf)?;
+
+        return Ok(shares);
+    }
+
+    /// Replace every key slot of `kind` by a new one, in a transaction
+    fn replace_key_slots(
+        &self,
+        kind: KeySlotKind,
+        label: &str,
+        secret: &[u8],
+        kdf: &KdfParams,
+    ) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute("DELETE FROM KeySlot WHERE kind = ?1;", [kind.to_i64()])?;
+        insert_key_slot(
+            &tx,
+            kind,
+            label,
+            secret,
+            kdf,
+            (&self.data_key, self.vault_id.as_ref()),
+            None,
+        )?;
+        tx.commit()?;
+
+        return Ok(());
     }
-}
 
+    /// Replace the master password slot, e.g. after opening the database
+    /// with its recovery key. Other password slots are kept. Vaults
+    /// requiring a key file need it in `new_credentials`.
+    pub fn reset_master_password(
+        &mut self,
+        new_credentials: &Credentials,
+        kdf: KdfParams,
+    ) -> Result<()> {
+        if self.is_legacy() {
+            return Err(Error::InvalidInput(
+                "legacy vaults have no master password slot".into(),
+            ));
+        }
+        let key_file = new_credentials.key_file().or(self.key_file.as_ref());
+        if key_file.is_none() && self.requires_key_file()? {
+            return Err(Error::KeyFileRequired);
+        }
+        let key_file = key_file.cloned();
+
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "DELETE FROM KeySlot WHERE kind = ?1 AND label = ?2;",
+            rusqlite::params![KeySlotKind::Password.to_i64(), Self::MASTER_PASSWORD_LABEL],
+        )?;
+        insert_key_slot(
+            &tx,
+            KeySlotKind::Password,
+            Self::MASTER_PASSWORD_LABEL,
+            new_credentials.password().as_bytes(),
+            &kdf,
+            (&self.data_key, self.vault_id.as_ref()),
+            key_file.as_ref(),
+        )?;
+        tx.commit()?;
+        self.key_file = key_file;
+
+        return Ok(());
+    }
+
+    /// Revoke a key slot. The last slot of a vault cannot be removed.
+    pub fn remove_key_slot(&self, slot_id: i64) -> Result<()> {
+        let slot_count: i64 = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM KeySlot;", [], |row| row.get(0))?;
+        if slot_count <= 1 {
+            return Err(Error::InvalidInput(
+                "the last key slot cannot be removed".into(),
+            ));
+        }
+
+        let deleted = self
+            .conn
+            .execute("DELETE FROM KeySlot WHERE id = ?1;", [slot_id])?;
+
+        if deleted == 0 {
+            return Err(Error::KeySlotNotFound(slot_id));
+        }
+
+        return Ok(());
+    }
+
+    /// Whether password slots also require the vault's key file
+    pub fn requires_key_file(&self) -> Result<bool> {
+        return Self::read_requires_key_file(&self.conn);
+    }
+
+    fn read_requires_key_file(conn: &Connection) -> Result<bool> {
+        let has_column: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('Metadata') WHERE name = 'requires_key_file';",
+            [],
+            |row| row.get(0),
+        )?;
+        if !has_column {
+            return Ok(false);
+        }
+
+        let requires_key_file =
+            conn.query_row("SELECT requires_key_file FROM Metadata;", [], |row| {
+                row.get(0)
+            })?;
+
+        return Ok(requires_key_file);
+    }
+
+    fn has_key_slots(conn: &Connection) -> Result<bool> {
+        let has_key_slots = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'KeySlot';",
+            [],
+            |row| row.get(0),
+        )?;
+
+        return Ok(has_key_slots);
+    }
+
+    fn has_wrapped_key_bound_column(conn: &Connection) -> Result<bool> {
+        let has_column = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('KeySlot') WHERE name = 'wrapped_key_bound';",
+            [],
+            |row| row.get(0),
+        )?;
+
+        return Ok(has_column);
+    }
+
+    fn has_kdf_columns(conn: &Connection) -> Result<bool> {
+        let has_kdf_columns = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('Metadata') WHERE name = 'kdf_salt';",
+            [],
+            |row| row.get(0),
+        )?;
+
+        return Ok(has_kdf_columns);
+    }
+
+    fn read_kdf_params(conn: &Connection) -> Result<Option<KdfParams>> {
+        if !Self::has_kdf_columns(conn)? {
+            return Ok(None);
+        }
+
+        let row: Option<(Vec<u8>, u32, u32, u32)> = conn
+            .query_row(
+                "
+SELECT kdf_salt, kdf_memory, kdf_iterations, kdf_parallelism
+FROM Metadata
+WHERE kdf_salt IS NOT NULL;",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .optional()?;
+
+        let Some((salt, memory_kib, iterations, parallelism)) = row else {
+            return Ok(None);
+        };
+        let salt: [u8; SALT_SIZE] = salt.as_slice().try_into().map_err(|_| Error::CorruptBlob)?;
+
+        return Ok(Some(KdfParams::new(
+            salt,
+            memory_kib,
+            iterations,
+            parallelism,
+        )));
+    }
+
+    pub fn root_group_id(&self) -> i64 {
+        return Self::ROOT_GROUP_ID;
+        //         return self
+        //             .conn
+        //             .query_row(
+        //                 "
+        // SELECT id
+        // FROM EntryGroup e
+        // WHERE e.id NOT IN (
+        //     SELECT child_id FROM EntryGroupParent
+        // );",
+        //                 [],
+        //                 |row| row.get(0),
+        //             )
+        //             .expect("there should be a group with no parent");
+    }
+
+    pub fn entry(&self, row_id: i64) -> Result<Entry> {
+        let entry_data = self
+            .decrypt_blob("Entry", "data", row_id)
+            .map_err(|err| match err {
+                Error::Sqlite(rusqlite::Error::QueryReturnedNoRows) => Error::EntryNotFound(row_id),
+                err => err,
+            })?;
+        let deserialized = ciborium::from_reader(entry_data.as_slice())?;
+        return Ok(Entry::new(row_id, deserialized));
+    }
+
+    /// Add an entry, its creation, modification and password change times
+    /// being set to now
+    pub fn insert_entry(&self, group_id: i64, entry: &EntryData) -> Result<i64> {
+        let mut entry = entry.clone();
+        let now = timestamp::now();
+        entry.created = Some(now);
+        entry.modified = Some(now);
+        entry.password_changed = Some(now);
+        entry.accessed = None;
+        let mut serialized = Zeroizing::new(Vec::new());
+        ciborium::into_writer(&entry, &mut *serialized)?;
+        let mut index = self.structure_index()?;
+        let stored_group_id = match index {
+            Some(_) => self.root_group_id(),
+            None => group_id,
+        };
+
+        let tx = self.conn.unchecked_transaction()?;
+        let entry_id = insert_encrypted(
+            &tx,
+            "INSERT INTO Entry(group_id, data) VALUES (?1, x'');",
+            [stored_group_id],
+            "Entry",
+            serialized.as_slice(),
+            &self.cipher,
+            self.vault_id.as_ref(),
+        )?;
+        if let Some(index) = &mut index {
+            index.entry_groups.insert(entry_id, group_id);
+            self.save_structure_index(index)?;
+        }
+        tx.commit()?;
+
+        return Ok(entry_id);
+    }
+
+    /// Replace the data of an existing entry, e.g. to rename it or to change
+    /// its password. The modification time is set to now, and so is the
+    /// password change time if the password differs; the other timestamps of
+    /// `entry` are ignored.
+    pub fn update_entry(&self, entry_id: i64, entry: &EntryData) -> Result<()> {
+        let current = self.entry(entry_id)?;
+        let current = current.data();
+        let mut entry = entry.clone();
+        let now = timestamp::now();
+        entry.created = current.created;
+        entry.modified = Some(now);
+        entry.password_changed = if entry.password() != current.password() {
+            Some(now)
+        } else {
+            current.password_changed
+        };
+        entry.accessed = current.accessed;
+
+        return self.write_entry(entry_id, &entry);
+    }
+
+    /// Record that secrets of the entries were read, e.g. printed, without
+    /// changing their modification time. Reads never record it on their own.
+    pub fn record_access(&self, entry_ids: &[i64]) -> Result<()> {
+        if entry_ids.is_empty() {
+            return Ok(());
+        }
+        let now = timestamp::now();
+        let tx = self.conn.unchecked_transaction()?;
+        for &entry_id in entry_ids {
+            let mut entry_data = self.entry(entry_id)?.data().clone();
+            entry_data.accessed = Some(now);
+            self.write_entry(entry_id, &entry_data)?;
+        }
+        tx.commit()?;
+
+        return Ok(());
+    }
+
+    /// Replace the data of an entry as is, timestamps included
+    fn write_entry(&self, entry_id: i64, entry: &EntryData) -> Result<()> {
+        let mut serialized = Zeroizing::new(Vec::new());
+        ciborium::into_writer(entry, &mut *serialized)?;
+        let updated = self.conn.execute(
+            "UPDATE Entry SET data = ?1 WHERE id = ?2;",
+            rusqlite::params![
+                encrypt_blob(
+                    serialized.as_slice(),
+                    &self.cipher,
+                    &self.associated_data("Entry", entry_id),
+                )?,
+                entry_id
+            ],
+        )?;
+
+        if updated == 0 {
+            return Err(Error::EntryNotFound(entry_id));
+        }
+
+        return Ok(());
+    }
+
+    /// Code of the entry's HOTP secret, the incremented counter being saved
+    /// along with the entry so that no code is given twice. This is not a
+    /// modification of the entry.
+    pub fn next_hotp_code(&self, entry_id: i64) -> Result<SecretString> {
+        let tx = self.conn.unchecked_transaction()?;
+        let mut entry_data = self.entry(entry_id)?.data().clone();
+        let code = entry_data
+            .otp_mut()
+            .ok_or_else(|| Error::InvalidInput("the entry has no OTP secret".into()))?
+            .next_hotp_code()?;
+        self.write_entry(entry_id, &entry_data)?;
+        tx.commit()?;
+
+        return Ok(code);
+    }
+
+    pub fn delete_entry(&self, entry_id: i64) -> Result<()> {
+        let mut index = self.structure_index()?;
+        let tx = self.conn.unchecked_transaction()?;
+        delete_entry_attachments(&tx, entry_id)?;
+        let deleted = tx.execute("DELETE FROM Entry WHERE id = ?1;", [entry_id])?;
+
+        if deleted == 0 {
+            return Err(Error::EntryNotFound(entry_id));
+        }
+        if let Some(index) = &mut index {
+            index.entry_groups.remove(&entry_id);
+            self.save_structure_index(index)?;
+        }
+        tx.commit()?;
+
+        return Ok(());
+    }
+
+    pub fn move_entry(&self, entry_id: i64, new_group_id: i64) -> Result<()> {
+        if !self.group_exists(new_group_id)? {
+            return Err(Error::GroupNotFound(new_group_id));
+        }
+
+        if let Some(mut index) = self.structure_index()? {
+            if !index.entry_groups.contains_key(&entry_id) {
+                return Err(Error::EntryNotFound(entry_id));
+            }
+            index.entry_groups.insert(entry_id, new_group_id);
+            return self.save_structure_index(&index);
+        }
+
+        let updated = self.conn.execute(
+            "UPDATE Entry SET group_id = ?1 WHERE id = ?2;",
+            [new_group_id, entry_id],
+        )?;
+
+        if updated == 0 {
+            return Err(Error::EntryNotFound(entry_id));
+        }
+
+        return Ok(());
+    }
+
+    fn entry_exists(&self, entry_id: i64) -> Result<bool> {
+        let exists = self.conn.query_row(
+            "SELECT COUNT(*) > 0 FROM Entry WHERE id = ?1;",
+            [entry_id],
+            |row| row.get(0),
+        )?;
+
+        return Ok(exists);
+    }
+
+    fn group_exists(&self, group_id: i64) -> Result<bool> {
+        let exists = self.conn.query_row(
+            "SELECT COUNT(*) > 0 FROM EntryGroup WHERE id = ?1;",
+            [group_id],
+            |row| row.get(0),
+        )?;
+
+        return Ok(exists);
+    }
+
+    pub fn entries(
+        &self,
+        name_filter: Option<&str>,
+        parent_group_id: Option<i64>,
+    ) -> Result<Vec<Entry>> {
+        // with a hidden structure, entries are filtered by group below
+        let index = self.structure_index()?;
+        let mut stmt;
+        let rows = if let (Some(parent_id), None) = (parent_group_id, &index) {
+            stmt = self.conn.prepare(
+                "
+SELECT id, data FROM Entry
+WHERE group_id = ?1;
+",
+            )?;
+            stmt.query([parent_id])?
+        } else {
+            stmt = self.conn.prepare("SELECT id, data FROM Entry")?;
+            stmt.query([])?
+        };
+        let rows = rows
+            .map(|row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(Error::from);
+        // stmt.query_map([parent_id], |row| Ok((row.get(0)?, row.get(1)?))).unwrap()
+
+        return rows
+            .filter_map(|(entry_id, blob_buf)| {
+                // if let Ok((entry_id, blob_buf)) = mapped_row {
+                if let (Some(parent_id), Some(index)) = (parent_group_id, &index) {
+                    if index.entry_groups.get(&entry_id) != Some(&parent_id) {
+                        return Ok(None);
+                    }
+                }
+                let decrypted = decrypt_blob(
+                    Vec::as_slice(&blob_buf),
+                    &self.cipher,
+                    &self.associated_data("Entry", entry_id),
+                )?;
+                let entry_data: EntryData = ciborium::from_reader(decrypted.as_slice())?;
+
+                if let Some(name_filter) = name_filter {
+                    if !entry_data.name().contains(name_filter) {
+                        return Ok(None);
+                    }
+                }
+                return Ok(Some(Entry::new(entry_id, entry_data)));
+                // } else {
+                //     return None;
+                // }
+            })
+            .collect();
+    }
+
+    pub fn groups(&self, parent_group_id: Option<i64>) -> Result<Vec<EntryGroup>> {
+        //         let (mut stmt, params) = if let Some(parent_id) = parent_group_id {
+        //             (
+        //                 self.conn
+        //                     .prepare("
+        // SELECT id, data FROM EntryGroup
+        // WHERE id IN (
+        //     SELECT child_id FROM EntryGroupParent
+        //     WHERE parent_id = ?1
+        // );")
+        //                     .unwrap(),
+        //                 vec![parent_id],
+        //             )
+        //             // stmt.query([]).unwrap()
+        //             // todo!()
+        //         } else {
+        //             (
+        //                 self.conn
+        //                     .prepare("SELECT id, data FROM EntryGroup")
+        //                     .unwrap(),
+        //                 vec![],
+        //             )
+        //             // stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap()
+        //         };
+        //         let rows = stmt.query(params.as_slice()).unwrap();
+        // with a hidden structure, groups are filtered by parent below
+        let index = self.structure_index()?;
+        let mut stmt;
+        let rows = if let (Some(parent_id), None) = (parent_group_id, &index) {
+            stmt = self.conn.prepare(
+                "
+SELECT id, data FROM EntryGroup
+WHERE id IN (
+    SELECT child_id FROM EntryGroupParent
+    WHERE parent_id = ?1
+);",
+            )?;
+            stmt.query([parent_id])
+        } else {
+            stmt = self.conn.prepare("SELECT id, data FROM EntryGroup;")?;
+            stmt.query([])
+        };
+
+        return rows?
+            .map(|row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(Error::from)
+            .filter(|(group_id, _): &(i64, Vec<u8>)| {
+                let (Some(parent_id), Some(index)) = (parent_group_id, &index) else {
+                    return Ok(true);
+                };
+                return Ok(index.group_parents.get(group_id) == Some(&parent_id));
+            })
+            .map(|(group_id, data_blob): (i64, Vec<u8>)| {
+                let decrypted = decrypt_blob(
+                    Vec::as_slice(&data_blob),
+                    &self.cipher,
+                    &self.associated_data("EntryGroup", group_id),
+                )?;
+                let group_data: EntryGroupData = ciborium::from_reader(decrypted.as_slice())?;
+
+                return Ok(EntryGroup::new(group_id, group_data));
+            })
+            .collect();
+    }
+
+    // TODO: use Option<i64> for parent id to not make querying DB beforehand
+    //   necessary, but that would mean not being able to create a root-level
+    //   group with this method.
+    pub fn insert_entry_group(
+        &mut self,
+        parent_group_id: i64,
+        group_data: &EntryGroupData,
+    ) -> Result<i64> {
+        let mut serialized: Vec<u8> = vec![];
+        let mut index = self.structure_index()?;
+        let tx = self.conn.unchecked_transaction()?;
+
+        ciborium::into_writer(group_data, &mut serialized)?;
+        let entry_group_id = insert_encrypted(
+            &tx,
+            "INSERT INTO EntryGroup(data) VALUES (x'');",
+            [],
+            "EntryGroup",
+            serialized.as_slice(),
+            &self.cipher,
+            self.vault_id.as_ref(),
+        )?;
+
+        match &mut index {
+            Some(index) => {
+                index.group_parents.insert(entry_group_id, parent_group_id);
+                self.save_structure_index(index)?;
+            }
+            None => {
+                tx.execute(
+                    "INSERT INTO EntryGroupParent(parent_id, child_id) VALUES (?1, ?2);",
+                    rusqlite::params![parent_group_id, entry_group_id,],
+                )?;
+            }
+        }
+
+        tx.commit()?;
+
+        return Ok(entry_group_id);
+    }
+
+    // TODO: refactor copied code from Database::insert_entry_group
+    pub fn insert_root_entry_group(&self, group_data: &EntryGroupData) -> Result<i64> {
+        let mut serialized: Vec<u8> = vec![];
+        ciborium::into_writer(group_data, &mut serialized)?;
+        self.conn.execute(
+            "INSERT INTO EntryGroup(id, data) VALUES (?1, ?2);", // TODO: use fixed id =1 or-1 or
+            // something
+            rusqlite::params![
+                Self::ROOT_GROUP_ID,
+                encrypt_blob(
+                    serialized.as_slice(),
+                    &self.cipher,
+                    &self.associated_data("EntryGroup", Self::ROOT_GROUP_ID),
+                )?,
+            ],
+        )?;
+
+        return Ok(self.conn.last_insert_rowid());
+    }
+
+    pub fn entry_group(&self, group_id: i64) -> Result<EntryGroup> {
+        let group_data =
+            self.decrypt_blob("EntryGroup", "data", group_id)
+                .map_err(|err| match err {
+                    Error::Sqlite(rusqlite::Error::QueryReturnedNoRows) => {
+                        Error::GroupNotFound(group_id)
+                    }
+                    err => err,
+                })?;
+        let deserialized = ciborium::from_reader(group_data.as_slice())?;
+        return Ok(EntryGroup::new(group_id, deserialized));
+    }
+
+    /// Replace the data of an existing group, e.g. to rename it.
+    pub fn update_entry_group(&self, group_id: i64, group_data: &EntryGroupData) -> Result<()> {
+        let mut serialized: Vec<u8> = vec![];
+        ciborium::into_writer(group_data, &mut serialized)?;
+        let updated = self.conn.execute(
+            "UPDATE EntryGroup SET data = ?1 WHERE id = ?2;",
+            rusqlite::params![
+                encrypt_blob(
+                    serialized.as_slice(),
+                    &self.cipher,
+                    &self.associated_data("EntryGroup", group_id),
+                )?,
+                group_id
+            ],
+        )?;
+
+        if updated == 0 {
+            return Err(Error::GroupNotFound(group_id));
+        }
+
+        return Ok(());
+    }
+
+    /// Move a group under a new parent group. The root group cannot be moved,
+    /// and a group cannot become its own ancestor.
+    pub fn move_entry_group(&mut self, group_id: i64, new_parent_id: i64) -> Result<()> {
+        if group_id == self.root_group_id() {
+            return Err(Error::InvalidInput("the root group cannot be moved".into()));
+        }
+        if !self.group_exists(group_id)? {
+            return Err(Error::GroupNotFound(group_id));
+        }
+        if !self.group_exists(new_parent_id)? {
+            return Err(Error::GroupNotFound(new_parent_id));
+        }
+
+        let parents = self.group_parents()?;
+        let mut ancestor = Some(new_parent_id);
+        while let Some(ancestor_id) = ancestor {
+            if ancestor_id == group_id {
+                return Err(Error::InvalidInput(format!(
+                    "group {} cannot be moved under its descendant {}",
+                    group_id, new_parent_id
+                )));
+            }
+            ancestor = parents.get(&ancestor_id).copied();
+        }
+
+        if let Some(mut index) = self.structure_index()? {
+            index.group_parents.insert(group_id, new_parent_id);
+            return self.save_structure_index(&index);
+        }
+
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "DELETE FROM EntryGroupParent WHERE child_id = ?1;",
+            [group_id],
+        )?;
+        tx.execute(
+            "INSERT INTO EntryGroupParent(parent_id, child_id) VALUES (?1, ?2);",
+            [new_parent_id, group_id],
+        )?;
+        tx.commit()?;
+
+        return Ok(());
+    }
+
+    /// Resolve a group given by id or by path from the root group.
+    pub fn resolve_group(&self, group: &ItemRef) -> Result<i64> {
+        let path_str = match group {
+            ItemRef::Id(id) => return Ok(*id),
+            ItemRef::Path(path_str) => path_str,
+        };
+
+        let mut group_id = self.root_group_id();
+        for name in path::split(path_str)? {
+            group_id = self.child_group(group_id, &name, path_str)?;
+        }
+
+        return Ok(group_id);
+    }
+
+    /// Resolve an entry given by id or by path, the last name of the path
+    /// being the entry name and the others its group path.
+    pub fn resolve_entry(&self, entry: &ItemRef) -> Result<i64> {
+        let path_str = match entry {
+            ItemRef::Id(id) => return Ok(*id),
+            ItemRef::Path(path_str) => path_str,
+        };
+
+        let mut names = path::split(path_str)?;
+        let entry_name = names
+            .pop()
+            .ok_or_else(|| Error::InvalidInput("missing entry name in path".into()))?;
+        let mut group_id = self.root_group_id();
+        for name in names {
+            group_id = self.child_group(group_id, &name, path_str)?;
+        }
+
+        let matched: Vec<i64> = self
+            .entries(None, Some(group_id))?
+            .into_iter()
+            .filter(|entry| entry.data().name() == entry_name)
+            .map(|entry| entry.id())
+            .collect();
+        return match matched.as_slice() {
+            [entry_id] => Ok(*entry_id),
+            [] => Err(Error::PathNotFound(path_str.clone())),
+            _ => Err(Error::AmbiguousPath(path_str.clone())),
+        };
+    }
+
+    /// Path of a group from the root group, with escaped names
+    pub fn group_path(&self, group_id: i64) -> Result<String> {
+        let parents = self.group_parents()?;
+        let mut names = vec![];
+        let mut current = group_id;
+        while let Some(&parent_id) = parents.get(&current) {
+            names.push(self.entry_group(current)?.data().name().to_string());
+            current = parent_id;
+        }
+        names.reverse();
+
+        return Ok(path::join(names.iter().map(String::as_str)));
+    }
+
+    fn child_group(&self, parent_id: i64, name: &str, path_str: &str) -> Result<i64> {
+        let matched: Vec<i64> = self
+            .groups(Some(parent_id))?
+            .into_iter()
+            .filter(|group| group.data().name() == name)
+            .map(|group| group.id())
+            .collect();
+        return match matched.as_slice() {
+            [group_id] => Ok(*group_id),
+            [] => Err(Error::PathNotFound(path_str.into())),
+            _ => Err(Error::AmbiguousPath(path_str.into())),
+        };
+    }
+
+    /// Delete a group. If `recursive`, its descendant groups and all their
+    /// entries are deleted in the same transaction, otherwise the group must
+    /// be empty. The root group cannot be deleted.
+    pub fn delete_entry_group(&mut self, group_id: i64, recursive: bool) -> Result<()> {
+        if group_id == self.root_group_id() {
+            return Err(Error::InvalidInput(
+                "the root group cannot be deleted".into(),
+            ));
+        }
+        if !self.group_exists(group_id)? {
+            return Err(Error::GroupNotFound(group_id));
+        }
+
+        let parents = self.group_parents()?;
+        let group_ids = descendant_groups(&parents, group_id);
+        if !recursive && (group_ids.len() > 1 || self.group_entry_count(group_id)? > 0) {
+            return Err(Error::InvalidInput(format!(
+                "group {} is not empty",
+                group_id
+            )));
+        }
+
+        let mut index = self.structure_index()?;
+        let tx = self.conn.unchecked_transaction()?;
+        for id in &group_ids {
+            match &mut index {
+                Some(index) => {
+                    let entry_ids: Vec<i64> = index
+                        .entry_groups
+                        .iter()
+                        .filter(|(_, group)| *group == id)
+                        .map(|(&entry_id, _)| entry_id)
+                        .collect();
+                    for entry_id in entry_ids {
+                        delete_entry_attachments(&tx, entry_id)?;
+                        tx.execute("DELETE FROM Entry WHERE id = ?1;", [entry_id])?;
+                        index.entry_groups.remove(&entry_id);
+                    }
+                    index.group_parents.remove(id);
+                }
+                None => {
+                    let entry_ids: Vec<i64> = tx
+                        .prepare("SELECT id FROM Entry WHERE group_id = ?1;")?
+                        .query_map([id], |row| row.get(0))?
+                        .collect::<rusqlite::Result<_>>()?;
+                    for entry_id in entry_ids {
+                        delete_entry_attachments(&tx, entry_id)?;
+                    }
+                    tx.execute("DELETE FROM Entry WHERE group_id = ?1;", [id])?;
+                    tx.execute(
+                        "DELETE FROM EntryGroupParent WHERE child_id = ?1 OR parent_id = ?1;",
+                        [id],
+                    )?;
+                }
+            }
+            tx.execute("DELETE FROM EntryGroup WHERE id = ?1;", [id])?;
+        }
+        if let Some(index) = &index {
+            self.save_structure_index(index)?;
+        }
+        tx.commit()?;
+
+        return Ok(());
+    }
+
+    fn group_entry_count(&self, group_id: i64) -> Result<i64> {
+        if let Some(index) = self.structure_index()? {
+            let count = index
+                .entry_groups
+                .values()
+                .filter(|&&entry_group_id| entry_group_id == group_id)
+                .count();
+            return Ok(count as i64);
+        }
+
+        let count = self.conn.query_row(
+            "SELECT COUNT(*) FROM Entry WHERE group_id = ?1;",
+            [group_id],
+            |row| row.get(0),
+        )?;
+
+        return Ok(count);
+    }
+
+    /// Parent id of every group except the root group, by child id
+    fn group_parents(&self) -> Result<HashMap<i64, i64>> {
+        if let Some(index) = self.structure_index()? {
+            return Ok(index.group_parents);
+        }
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT child_id, parent_id FROM EntryGroupParent;")?;
+        let parents = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+
+        return Ok(parents);
+    }
+
+    /// Group id of every entry, by entry id
+    fn entry_groups(&self) -> Result<HashMap<i64, i64>> {
+        if let Some(index) = self.structure_index()? {
+            return Ok(index.entry_groups);
+        }
+
+        let mut stmt = self.conn.prepare("SELECT id, group_id FROM Entry;")?;
+        let entry_groups = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+
+        return Ok(entry_groups);
+    }
+
+    /// Whether the group of each entry and the group tree are encrypted
+    /// instead of stored in clear, see [`Database::set_structure_hidden`].
+    pub fn hides_structure(&self) -> Result<bool> {
+        let hidden =
+            self.conn
+                .query_row("SELECT COUNT(*) > 0 FROM StructureIndex;", [], |row| {
+                    row.get(0)
+                })?;
+
+        return Ok(hidden);
+    }
+
+    /// Move the group of each entry and the group tree from clear columns to
+    /// a single encrypted index, or back. Once hidden, only the number of
+    /// groups and entries is visible without the data key.
+    pub fn set_structure_hidden(&mut self, hidden: bool) -> Result<()> {
+        let index = self.structure_index()?;
+        if index.is_some() == hidden {
+            return Ok(());
+        }
+
+        let tx = self.conn.unchecked_transaction()?;
+        match index {
+            None => {
+                let index = StructureIndex {
+                    entry_groups: self.entry_groups()?,
+                    group_parents: self.group_parents()?,
+                };
+                self.save_structure_index(&index)?;
+                tx.execute("DELETE FROM EntryGroupParent;", [])?;
+                tx.execute("UPDATE Entry SET group_id = ?1;", [self.root_group_id()])?;
+            }
+            Some(index) => {
+                tx.execute("DELETE FROM StructureIndex;", [])?;
+                for (child_id, parent_id) in &index.group_parents {
+                    tx.execute(
+                        "INSERT INTO EntryGroupParent(parent_id, child_id) VALUES (?1, ?2);",
+                        [parent_id, child_id],
+                    )?;
+                }
+                for (entry_id, group_id) in &index.entry_groups {
+                    tx.execute(
+                        "UPDATE Entry SET group_id = ?1 WHERE id = ?2;",
+                        [group_id, entry_id],
+                    )?;
+                }
+            }
+        }
+        tx.commit()?;
+
+        return Ok(());
+    }
+
+    /// Decrypted structure index, `None` if the structure is stored in clear
+    fn structure_index(&self) -> Result<Option<StructureIndex>> {
+        let blob: Option<Vec<u8>> = self
+            .conn
+            .query_row(
+                "SELECT data FROM StructureIndex WHERE id = ?1;",
+                [Self::STRUCTURE_INDEX_ROW_ID],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(blob) = blob else {
+            return Ok(None);
+        };
+
+        let decrypted = decrypt_blob(
+            blob.as_slice(),
+            &self.cipher,
+            &self.associated_data("StructureIndex", Self::STRUCTURE_INDEX_ROW_ID),
+        )?;
+        return Ok(Some(ciborium::from_reader(decrypted.as_slice())?));
+    }
+
+    fn save_structure_index(&self, index: &StructureIndex) -> Result<()> {
+        let mut serialized: Vec<u8> = vec![];
+        ciborium::into_writer(index, &mut serialized)?;
+        self.conn.execute(
+            "INSERT OR REPLACE INTO StructureIndex(id, data) VALUES (?1, ?2);",
+            rusqlite::params![
+                Self::STRUCTURE_INDEX_ROW_ID,
+                encrypt_blob(
+                    serialized.as_slice(),
+                    &self.cipher,
+                    &self.associated_data("StructureIndex", Self::STRUCTURE_INDEX_ROW_ID),
+                )?,
+            ],
+        )?;
+
+        return Ok(());
+    }
+
+    /// Tags of the entries, with the number of entries having each, sorted by
+    /// tag
+    pub fn tags(&self) -> Result<Vec<(String, usize)>> {
+        let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+        for entry in self.entries(None, None)? {
+            for tag in entry.data().tags() {
+                *counts.entry(tag.to_string()).or_default() += 1;
+            }
+        }
+
+        return Ok(counts.into_iter().collect());
+    }
+
+    /// Attachments of an entry, sorted by name
+    pub fn attachments(&self, entry_id: i64) -> Result<Vec<Attachment>> {
+        if !self.entry_exists(entry_id)? {
+            return Err(Error::EntryNotFound(entry_id));
+        }
+        let mut attachments: Vec<Attachment> = self
+            .stored_attachments(entry_id)?
+            .into_iter()
+            .map(|(attachment_id, stored)| {
+                Attachment::new(attachment_id, stored.name.into(), stored.size)
+            })
+            .collect();
+        attachments.sort_by(|a, b| a.name().cmp(b.name()));
+
+        return Ok(attachments);
+    }
+
+    /// Attach the content read from `reader` to an entry, encrypting it chunk
+    /// by chunk so that only one chunk is held in memory. The name must be
+    /// unique among the entry's attachments, and the content is limited to
+    /// [`Self::MAX_ATTACHMENT_SIZE`] and to what is left of
+    /// [`Self::MAX_ENTRY_ATTACHMENTS_SIZE`] for the entry.
+    pub fn insert_attachment(
+        &self,
+        entry_id: i64,
+        name: &str,
+        mut reader: impl Read,
+    ) -> Result<i64> {
+        if name.is_empty() {
+            return Err(Error::InvalidInput("empty attachment name".into()));
+        }
+        if !self.entry_exists(entry_id)? {
+            return Err(Error::EntryNotFound(entry_id));
+        }
+        let existing = self.stored_attachments(entry_id)?;
+        if existing.iter().any(|(_, stored)| stored.name == name) {
+            return Err(Error::InvalidInput(format!(
+                "the entry already has an attachment named \"{}\"",
+                name
+            )));
+        }
+        let entry_size: u64 = existing.iter().map(|(_, stored)| stored.size).sum();
+        let max_size = Self::MAX_ATTACHMENT_SIZE
+            .min(Self::MAX_ENTRY_ATTACHMENTS_SIZE.saturating_sub(entry_size));
+
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO Attachment(entry_id, data) VALUES (?1, x'');",
+            [entry_id],
+        )?;
+        let attachment_id = tx.last_insert_rowid();
+
+        let mut size = 0u64;
+        let mut chunk_count = 0u32;
+        let mut chunk = Zeroizing::new(Vec::with_capacity(
+            CHUNK_PREFIX_SIZE + Self::ATTACHMENT_CHUNK_SIZE,
+        ));
+        loop {
+            chunk.clear();
+            chunk.extend_from_slice(&chunk_prefix(attachment_id, chunk_count));
+            let read = (&mut reader)
+                .take(Self::ATTACHMENT_CHUNK_SIZE as u64)
+                .read_to_end(&mut chunk)?;
+            // an empty attachment still gets an empty chunk
+            if read == 0 && chunk_count > 0 {
+                break;
+            }
+            size += read as u64;
+            if size > max_size {
+                return Err(Error::InvalidInput(format!(
+                    "attachments are limited to {} MiB each and {} MiB per entry",
+                    Self::MAX_ATTACHMENT_SIZE / (1024 * 1024),
+                    Self::MAX_ENTRY_ATTACHMENTS_SIZE / (1024 * 1024)
+                )));
+            }
+
+            insert_encrypted(
+                &tx,
+                "INSERT INTO AttachmentChunk(attachment_id, position, data) VALUES (?1, ?2, x'');",
+                rusqlite::params![attachment_id, chunk_count],
+                "AttachmentChunk",
+                chunk.as_slice(),
+                &self.cipher,
+                self.vault_id.as_ref(),
+            )?;
+            chunk_count += 1;
+            if read < Self::ATTACHMENT_CHUNK_SIZE {
+                break;
+            }
+        }
+
+        let stored = StoredAttachment {
+            name: name.into(),
+            size,
+            chunk_count,
+        };
+        let mut serialized: Vec<u8> = vec![];
+        ciborium::into_writer(&stored, &mut serialized)?;
+        tx.execute(
+            "UPDATE Attachment SET data = ?1 WHERE id = ?2;",
+            rusqlite::params![
+                encrypt_blob(
+                    serialized.as_slice(),
+                    &self.cipher,
+                    &self.associated_data("Attachment", attachment_id),
+                )?,
+                attachment_id
+            ],
+        )?;
+        tx.commit()?;
+
+        return Ok(attachment_id);
+    }
+
+    /// Decrypt an attachment of an entry into `writer`, chunk by chunk, and
+    /// return its size. Chunks that were removed, reordered or moved from
+    /// another attachment fail with [`Error::CorruptBlob`], possibly after
+    /// the preceding chunks were written.
+    pub fn read_attachment(
+        &self,
+        entry_id: i64,
+        name: &str,
+        mut writer: impl Write,
+    ) -> Result<u64> {
+        let (attachment_id, stored) = self.find_attachment(entry_id, name)?;
+
+        let mut stmt = self.conn.prepare(
+            "SELECT id, data FROM AttachmentChunk WHERE attachment_id = ?1 ORDER BY position;",
+        )?;
+        let mut rows = stmt.query([attachment_id])?;
+        let mut position = 0u32;
+        let mut size = 0u64;
+        while let Some(row) = rows.next()? {
+            let chunk_id: i64 = row.get(0)?;
+            let blob = row.get_ref(1)?.as_blob().map_err(|_| Error::CorruptBlob)?;
+            let chunk = decrypt_blob(
+                blob,
+                &self.cipher,
+                &self.associated_data("AttachmentChunk", chunk_id),
+            )?;
+            let content = chunk
+                .strip_prefix(chunk_prefix(attachment_id, position).as_slice())
+                .ok_or(Error::CorruptBlob)?;
+            writer.write_all(content)?;
+            position += 1;
+            size += content.len() as u64;
+        }
+        if position != stored.chunk_count || size != stored.size {
+            return Err(Error::CorruptBlob);
+        }
+        writer.flush()?;
+
+        return Ok(size);
+    }
+
+    pub fn delete_attachment(&self, entry_id: i64, name: &str) -> Result<()> {
+        let (attachment_id, _) = self.find_attachment(entry_id, name)?;
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "DELETE FROM AttachmentChunk WHERE attachment_id = ?1;",
+            [attachment_id],
+        )?;
+        tx.execute("DELETE FROM Attachment WHERE id = ?1;", [attachment_id])?;
+        tx.commit()?;
+
+        return Ok(());
+    }
+
+    fn find_attachment(&self, entry_id: i64, name: &str) -> Result<(i64, StoredAttachment)> {
+        if !self.entry_exists(entry_id)? {
+            return Err(Error::EntryNotFound(entry_id));
+        }
+        return self
+            .stored_attachments(entry_id)?
+            .into_iter()
+            .find(|(_, stored)| stored.name == name)
+            .ok_or_else(|| Error::AttachmentNotFound(name.into()));
+    }
+
+    fn stored_attachments(&self, entry_id: i64) -> Result<Vec<(i64, StoredAttachment)>> {
+        return self
+            .conn
+            .prepare("SELECT id, data FROM Attachment WHERE entry_id = ?1;")?
+            .query_map([entry_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .map(|row| {
+                let (attachment_id, blob): (i64, Vec<u8>) = row?;
+                let decrypted = decrypt_blob(
+                    blob.as_slice(),
+                    &self.cipher,
+                    &self.associated_data("Attachment", attachment_id),
+                )?;
+                let stored: StoredAttachment = ciborium::from_reader(decrypted.as_slice())?;
+                Ok((attachment_id, stored))
+            })
+            .collect();
+    }
+
+    /// Saved password policies with their names, sorted by name
+    pub fn policies(&self) -> Result<Vec<(String, PasswordPolicy)>> {
+        let mut policies: Vec<(String, PasswordPolicy)> = self
+            .stored_policies()?
+            .into_iter()
+            .map(|(_, stored)| (stored.name, stored.policy))
+            .collect();
+        policies.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        return Ok(policies);
+    }
+
+    pub fn policy(&self, name: &str) -> Result<PasswordPolicy> {
+        return self
+            .stored_policies()?
+            .into_iter()
+            .find(|(_, stored)| stored.name == name)
+            .map(|(_, stored)| stored.policy)
+            .ok_or_else(|| Error::PolicyNotFound(name.into()));
+    }
+
+    /// Save a policy under the given name, replacing the policy with the
+    /// same name if any.
+    pub fn save_policy(&self, name: &str, policy: &PasswordPolicy) -> Result<()> {
+        let stored = StoredPolicy {
+            name: name.into(),
+            policy: policy.clone(),
+        };
+        let mut serialized: Vec<u8> = vec![];
+        ciborium::into_writer(&stored, &mut serialized)?;
+
+        let existing = self
+            .stored_policies()?
+            .into_iter()
+            .find(|(_, stored)| stored.name == name);
+        match existing {
+            Some((policy_id, _)) => {
+                let blob = encrypt_blob(
+                    serialized.as_slice(),
+                    &self.cipher,
+                    &self.associated_data("PasswordPolicy", policy_id),
+                )?;
+                self.conn.execute(
+                    "UPDATE PasswordPolicy SET data = ?1 WHERE id = ?2;",
+                    rusqlite::params![blob, policy_id],
+                )?;
+            }
+            None => {
+                let tx = self.conn.unchecked_transaction()?;
+                insert_encrypted(
+                    &tx,
+                    "INSERT INTO PasswordPolicy(data) VALUES (x'');",
+                    [],
+                    "PasswordPolicy",
+                    serialized.as_slice(),
+                    &self.cipher,
+                    self.vault_id.as_ref(),
+                )?;
+                tx.commit()?;
+            }
+        };
+
+        return Ok(());
+    }
+
+    pub fn remove_policy(&self, name: &str) -> Result<()> {
+        let (policy_id, _) = self
+            .stored_policies()?
+            .into_iter()
+            .find(|(_, stored)| stored.name == name)
+            .ok_or_else(|| Error::PolicyNotFound(name.into()))?;
+        self.conn
+            .execute("DELETE FROM PasswordPolicy WHERE id = ?1;", [policy_id])?;
+
+        return Ok(());
+    }
+
+    fn stored_policies(&self) -> Result<Vec<(i64, StoredPolicy)>> {
+        return self
+            .conn
+            .prepare("SELECT id, data FROM PasswordPolicy;")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .map(|row| {
+                let (policy_id, blob): (i64, Vec<u8>) = row?;
+                let decrypted = decrypt_blob(
+                    blob.as_slice(),
+                    &self.cipher,
+                    &self.associated_data("PasswordPolicy", policy_id),
+                )?;
+                let stored: StoredPolicy = ciborium::from_reader(decrypted.as_slice())?;
+                Ok((policy_id, stored))
+            })
+            .collect();
+    }
+
+    /// Associated data of the blob stored in the given row, see
+    /// [`associated_data`]
+    fn associated_data(&self, table: &str, row_id: i64) -> Vec<u8> {
+        return associated_data(self.vault_id.as_ref(), table, row_id);
+    }
+
+    fn decrypt_blob(&self, table: &str, column: &str, row_id: i64) -> Result<DecryptedMessage> {
+        let query = format!("SELECT \"{}\" FROM \"{}\" WHERE id = ?", column, table);
+        let blob_buf: Vec<u8> =
+            self.conn
+                .query_row(query.as_str(), rusqlite::params![row_id], |row| row.get(0))?;
+
+        return decrypt_blob(
+            blob_buf.as_slice(),
+            &self.cipher,
+            &self.associated_data(table, row_id),
+        );
+        // let blob = self
+        //     .conn
+        //     .blob_open(DatabaseName::Main, table, column, row_id, true)
+        //     .unwrap();
+        // return decrypt_blob(&blob, self.cipher);
+    }
+}
+
+/// Encrypted content of a PasswordPolicy row
+#[derive(Serialize, Deserialize)]
+struct StoredPolicy {
+    name: String,
+    policy: PasswordPolicy,
+}
+
+/// Encrypted content of an Attachment row, the content being in its chunks
+#[derive(Serialize, Deserialize)]
+struct StoredAttachment {
+    name: String,
+    size: u64,
+    chunk_count: u32,
+}
+
+/// Encrypted content of the StructureIndex row, for vaults hiding their
+/// structure
+#[derive(Serialize, Deserialize)]
+struct StructureIndex {
+    /// Group id of every entry, by entry id
+    entry_groups: HashMap<i64, i64>,
+    /// Parent id of every group except the root group, by child id
+    group_parents: HashMap<i64, i64>,
+}
+
+/// The given group followed by all of its descendants
+fn descendant_groups(parents: &HashMap<i64, i64>, group_id: i64) -> Vec<i64> {
+    let mut descendants = vec![group_id];
+    let mut i = 0;
+    while i < descendants.len() {
+        let parent_id = descendants[i];
+        descendants.extend(
+            parents
+                .iter()
+                .filter(|(_, &parent)| parent == parent_id)
+                .map(|(&child, _)| child),
+        );
+        i += 1;
+    }
+
+    return descendants;
+}
+
+/// Derive the key of a slot from its secret. Password slots of vaults that
+/// require a key file also mix in the key file.
+fn derive_slot_key(
+    kdf: &KdfParams,
+    kind: KeySlotKind,
+    secret: &[u8],
+    key_file: Option<&KeyFileHash>,
+) -> Result<CipherKey> {
+    let key_file = key_file
+        .filter(|_| kind == KeySlotKind::Password)
+        .map(KeyFileHash::as_bytes);
+    return Ok(kdf.derive_key_with_secret(secret, key_file, &[])?);
+}
+
+/// Wrap the data key of the vault `vault_id` with a key derived from `secret`
+/// and store it in a new key slot. Returns the slot id and the cipher of the
+/// slot key.
+fn insert_key_slot(
+    conn: &Connection,
+    kind: KeySlotKind,
+    label: &str,
+    secret: &[u8],
+    kdf: &KdfParams,
+    (data_key, vault_id): (&CipherKey, Option<&VaultId>),
+    key_file: Option<&KeyFileHash>,
+) -> Result<(i64, Cipher)> {
+    let slot_key = derive_slot_key(kdf, kind, secret, key_file)?;
+    let slot_cipher = Cipher::new(&slot_key);
+    let slot_id =
+        insert_wrapped_key_slot(conn, kind, label, kdf, &slot_cipher, (data_key, vault_id))?;
+
+    return Ok((slot_id, slot_cipher));
+}
+
+/// Store a new key slot wrapping the data key of the vault `vault_id` with
+/// `slot_cipher`. Returns the slot id.
+fn insert_wrapped_key_slot(
+    conn: &Connection,
+    kind: KeySlotKind,
+    label: &str,
+    kdf: &KdfParams,
+    slot_cipher: &Cipher,
+    (data_key, vault_id): (&CipherKey, Option<&VaultId>),
+) -> Result<i64> {
+    // the slot id is part of the wrapped key's header and associated data,
+    // known once inserted
+    conn.execute(
+        "
+INSERT INTO KeySlot(
+    kind, label, kdf_salt, kdf_memory, kdf_iterations, kdf_parallelism, wrapped_key
+)
+VALUES (?1, ?2, ?3, ?4, ?5, ?6, x'');",
+        rusqlite::params![
+            kind.to_i64(),
+            label,
+            kdf.salt().as_slice(),
+            kdf.memory_kib(),
+            kdf.iterations(),
+            kdf.parallelism(),
+        ],
+    )?;
+    let slot_id = conn.last_insert_rowid();
+    conn.execute(
+        "UPDATE KeySlot SET wrapped_key = ?1, wrapped_key_bound = 1 WHERE id = ?2;",
+        rusqlite::params![
+            wrap_data_key(data_key, slot_cipher, slot_id, vault_id)?,
+            slot_id
+        ],
+    )?;
+
+    return Ok(slot_id);
+}
+
+/// Decrypt every blob in the given (table, column) pairs with the old cipher
+/// and vault id, and write it back encrypted with the new ones. Blobs written
+/// with another algorithm than the old cipher's are read too.
+fn reencrypt_blobs(
+    tx: &Transaction<'_>,
+    columns: &[(&str, &str)],
+    (old_cipher, old_vault_id): (&Cipher, Option<&VaultId>),
+    (new_cipher, new_vault_id): (&Cipher, Option<&VaultId>),
+) -> Result<()> {
+    for (table, column) in columns {
+        let select_ids = format!("SELECT rowid FROM \"{}\";", table);
+        let select = format!("SELECT \"{}\" FROM \"{}\" WHERE rowid = ?1;", column, table);
+        let update = format!(
+            "UPDATE \"{}\" SET \"{}\" = ?1 WHERE rowid = ?2;",
+            table, column
+        );
+        let row_ids: Vec<i64> = tx
+            .prepare(select_ids.as_str())
+            .and_then(|mut stmt| stmt.query_map([], |row| row.get(0))?.collect())?;
+
+        // one blob at a time, attachment chunks adding up to a lot of data
+        for row_id in row_ids {
+            let blob: Vec<u8> = tx.query_row(select.as_str(), [row_id], |row| row.get(0))?;
+            let decrypted = decrypt_blob_with_any_algorithm(
+                blob.as_slice(),
+                old_cipher,
+                &associated_data(old_vault_id, table, row_id),
+            )?;
+            let encrypted = encrypt_blob(
+                decrypted.as_slice(),
+                new_cipher,
+                &associated_data(new_vault_id, table, row_id),
+            )?;
+            tx.execute(update.as_str(), rusqlite::params![encrypted, row_id])?;
+        }
+    }
+
+    return Ok(());
+}
+
+/// Delete the attachments of an entry with their chunks
+fn delete_entry_attachments(conn: &Connection, entry_id: i64) -> Result<()> {
+    conn.execute(
+        "
+DELETE FROM AttachmentChunk
+WHERE attachment_id IN (SELECT id FROM Attachment WHERE entry_id = ?1);",
+        [entry_id],
+    )?;
+    conn.execute("DELETE FROM Attachment WHERE entry_id = ?1;", [entry_id])?;
+    return Ok(());
+}
+
+/// Size of the attachment id and position starting each decrypted chunk
+const CHUNK_PREFIX_SIZE: usize = 8 + 4;
+
+/// Start of an attachment chunk before encryption, binding the chunk to its
+/// attachment and position: the associated data only binds it to its row.
+fn chunk_prefix(attachment_id: i64, position: u32) -> [u8; CHUNK_PREFIX_SIZE] {
+    let mut prefix = [0u8; CHUNK_PREFIX_SIZE];
+    prefix[..8].copy_from_slice(&attachment_id.to_le_bytes());
+    prefix[8..].copy_from_slice(&position.to_le_bytes());
+    return prefix;
+}
+
+/// Algorithm of the vault's data, AES-256-GCM for vaults older than the
+/// column, which is read before migrating
+fn read_cipher_algorithm(conn: &Connection) -> Result<CipherAlgorithm> {
+    let has_column: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('Metadata') WHERE name = 'cipher';",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_column {
+        return Ok(CipherAlgorithm::Aes256Gcm);
+    }
+
+    let id: i64 = conn.query_row("SELECT cipher FROM Metadata;", [], |row| row.get(0))?;
+    return CipherAlgorithm::from_id(id).ok_or(Error::CorruptBlob);
+}
+
+/// Insert a row with `insert`, which must leave its `data` column empty, then
+/// fill it with `data` encrypted for the new row id. The row id is part of
+/// the associated data, so it must be known before encrypting. Run it in a
+/// transaction so that no empty row is left behind on failure.
+fn insert_encrypted(
+    conn: &Connection,
+    insert: &str,
+    params: impl rusqlite::Params,
+    table: &str,
+    data: &[u8],
+    cipher: &Cipher,
+    vault_id: Option<&VaultId>,
+) -> Result<i64> {
+    conn.execute(insert, params)?;
+    let row_id = conn.last_insert_rowid();
+
+    let update = format!("UPDATE \"{}\" SET data = ?1 WHERE rowid = ?2;", table);
+    conn.execute(
+        update.as_str(),
+        rusqlite::params![
+            encrypt_blob(data, cipher, &associated_data(vault_id, table, row_id))?,
+            row_id
+        ],
+    )?;
+
+    return Ok(row_id);
+}
+
+/// Read the vault id, missing from vaults older than schema version 2
+fn read_vault_id(conn: &Connection) -> Result<Option<VaultId>> {
+    let has_vault_id: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('Metadata') WHERE name = 'vault_id';",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_vault_id {
+        return Ok(None);
+    }
+
+    let vault_id: Option<Vec<u8>> =
+        conn.query_row("SELECT vault_id FROM Metadata;", [], |row| row.get(0))?;
+    let Some(vault_id) = vault_id else {
+        return Ok(None);
+    };
+    let vault_id: VaultId = vault_id
+        .as_slice()
+        .try_into()
+        .map_err(|_| Error::CorruptBlob)?;
+
+    return Ok(Some(vault_id));
+}
+
+/// Associated data binding a blob to its vault, table and row, so that a
+/// blob copied to another row or vault fails to decrypt. It is empty for
+/// vaults without an id.
+fn associated_data(vault_id: Option<&VaultId>, table: &str, row_id: i64) -> Vec<u8> {
+    let Some(vault_id) = vault_id else {
+        return vec![];
+    };
+
+    let mut associated_data = vault_id.to_vec();
+    associated_data.extend_from_slice(table.as_bytes());
+    associated_data.push(0);
+    associated_data.extend_from_slice(&row_id.to_le_bytes());
+    return associated_data;
+}
+
+/// Encrypt data with the vault data key's cipher and return the blob: a
+/// [`BlobHeader`], the nonce, then the encrypted data.
+fn encrypt_blob(data: &[u8], cipher: &Cipher, associated_data: &[u8]) -> Result<Vec<u8>> {
+    return encrypt_blob_with_header(data, cipher, blob::DATA_KEY_SLOT_ID, associated_data);
+}
+
+/// Encrypt the data key for the key slot `slot_id`, bound to the vault and
+/// the slot with the associated data of its KeySlot row
+fn wrap_data_key(
+    data_key: &CipherKey,
+    slot_cipher: &Cipher,
+    slot_id: i64,
+    vault_id: Option<&VaultId>,
+) -> Result<Vec<u8>> {
+    let header_slot_id = u32::try_from(slot_id)
+        .map_err(|_| Error::InvalidInput(format!("key slot id {} out of range", slot_id)))?;
+    return encrypt_blob_with_header(
+        data_key.as_ref(),
+        slot_cipher,
+        header_slot_id,
+        &associated_data(vault_id, "KeySlot", slot_id),
+    );
+}
+
+fn encrypt_blob_with_header(
+    data: &[u8],
+    cipher: &Cipher,
+    key_slot_id: u32,
+    associated_data: &[u8],
+) -> Result<Vec<u8>> {
+    let header = BlobHeader::new(cipher.algorithm(), key_slot_id).to_bytes();
+    let msg = cipher.encrypt(data, &[header.as_slice(), associated_data].concat())?;
+
+    let mut blob = Vec::with_capacity(header.len() + msg.nonce().len() + msg.ciphertext().len());
+    blob.extend_from_slice(&header);
+    blob.extend_from_slice(msg.nonce());
+    blob.extend_from_slice(msg.ciphertext());
+    return Ok(blob);
+}
+
+/// Decrypt a blob written by [`encrypt_blob`] with the data key and the
+/// vault's algorithm, or a header-less blob.
+fn decrypt_blob(
+    blob_buf: &[u8],
+    cipher: &Cipher,
+    associated_data: &[u8],
+) -> Result<DecryptedMessage> {
+    return decrypt_blob_from_slot(
+        blob_buf,
+        cipher,
+        blob::DATA_KEY_SLOT_ID,
+        false,
+        associated_data,
+    );
+}
+
+/// Decrypt a blob written with the data key by any algorithm, for
+/// re-encrypting it with the vault's.
+fn decrypt_blob_with_any_algorithm(
+    blob_buf: &[u8],
+    cipher: &Cipher,
+    associated_data: &[u8],
+) -> Result<DecryptedMessage> {
+    return decrypt_blob_from_slot(
+        blob_buf,
+        cipher,
+        blob::DATA_KEY_SLOT_ID,
+        true,
+        associated_data,
+    );
+}
+
+/// Decrypt the data key wrapped by [`wrap_data_key`] for the slot `slot_id`
+fn unwrap_data_key(
+    wrapped_key: &[u8],
+    slot_cipher: &Cipher,
+    slot_id: i64,
+    associated_data: &[u8],
+) -> Result<DecryptedMessage> {
+    let header_slot_id = u32::try_from(slot_id).map_err(|_| Error::CorruptBlob)?;
+    return decrypt_blob_from_slot(
+        wrapped_key,
+        slot_cipher,
+        header_slot_id,
+        false,
+        associated_data,
+    );
+}
+
+/// Decrypt a blob whose header names the key of the slot `key_slot_id` and
+/// `cipher`'s algorithm, or any algorithm if `any_algorithm`, or a
+/// header-less blob with `cipher`'s algorithm.
+fn decrypt_blob_from_slot(
+    blob_buf: &[u8],
+    cipher: &Cipher,
+    key_slot_id: u32,
+    any_algorithm: bool,
+    associated_data: &[u8],
+) -> Result<DecryptedMessage> {
+    let Some((header, payload)) = BlobHeader::parse(blob_buf) else {
+        return decrypt_payload(blob_buf, cipher, associated_data);
+    };
+    if header.key_slot_id != key_slot_id {
+        return Err(Error::CorruptBlob);
+    }
+
+    let header_associated_data = [&blob_buf[..BlobHeader::SIZE], associated_data].concat();
+    if header.cipher == cipher.algorithm() {
+        return decrypt_payload(payload, cipher, &header_associated_data);
+    }
+    if !any_algorithm {
+        return Err(Error::CorruptBlob);
+    }
+    let header_cipher = cipher.for_algorithm(header.cipher);
+    return decrypt_payload(payload, &header_cipher, &header_associated_data);
+}
+
+/// Decrypt `nonce || ciphertext`
+fn decrypt_payload(
+    payload: &[u8],
+    cipher: &Cipher,
+    associated_data: &[u8],
+) -> Result<DecryptedMessage> {
+    let nonce_size = cipher.algorithm().nonce_size();
+    if payload.len() < nonce_size {
+        return Err(Error::CorruptBlob);
+    }
+    let (nonce, ciphertext) = payload.split_at(nonce_size);
+
+    let decrypted_data = cipher.decrypt(ciphertext, nonce, associated_data)?;
+
+    return Ok(decrypted_data);
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::Read,
+        path::{Path, PathBuf},
+    };
+
+    use rusqlite::Connection;
+
+    use crate::{
+        encryption::{Cipher, CipherAlgorithm, CipherKey, KdfParams},
+        fixture::VaultBuilder,
+        generator::PasswordPolicy,
+        model::{
+            database::{
+                blob::BlobHeader, decrypt_blob, decrypt_blob_with_any_algorithm, encrypt_blob,
+                encrypt_blob_with_header, migration, unwrap_data_key, wrap_data_key, Database,
+            },
+            path::ItemRef,
+            Credentials, EntryData, EntryGroupData, KeySlotKind,
+        },
+        otp::{Otp, OtpKind},
+        timestamp, Error,
+    };
+
+    fn test_credentials() -> Credentials {
+        Credentials::new("user".into(), "password".into())
+    }
+
+    fn test_kdf() -> KdfParams {
+        KdfParams::with_random_salt(64, 1, 1)
+    }
+
+    fn test_db_path(dir: &Path) -> PathBuf {
+        return dir.join("test.sqlite");
+    }
+
+    /// Wrap the data key without associated data in the unlocked slot, and
+    /// drop the column marking bound slots, as before schema version 8
+    fn unbind_unlocked_slot(db: &Database) {
+        let slot = db.unlocked_slot.as_ref().unwrap();
+        let wrapped_key =
+            encrypt_blob_with_header(db.data_key.as_ref(), &slot.cipher, slot.id as u32, &[])
+                .unwrap();
+        db.conn
+            .execute(
+                "UPDATE KeySlot SET wrapped_key = ?1 WHERE id = ?2;",
+                rusqlite::params![wrapped_key, slot.id],
+            )
+            .unwrap();
+        db.conn
+            .execute_batch("ALTER TABLE KeySlot DROP COLUMN wrapped_key_bound;")
+            .unwrap();
+    }
+
+    #[test]
+    fn encrypt_then_decrypt_blob() {
+        let blob_data = b"some data";
+        let cipher_secret = b"secret key";
+        let test_cipher = Cipher::new(&cipher_secret.as_slice().into());
+        let encrypted = encrypt_blob(blob_data, &test_cipher, b"ad").unwrap();
+        let decrypted = decrypt_blob(encrypted.as_slice(), &test_cipher, b"ad").unwrap();
+
+        assert_eq!(decrypted.as_slice(), blob_data);
+    }
+
+    #[test]
+    fn decrypt_blobs_of_any_format() {
+        let key: CipherKey = b"secret key".as_slice().into();
+        let aes = Cipher::with_algorithm(&key, CipherAlgorithm::Aes256Gcm);
+        let chacha = Cipher::with_algorithm(&key, CipherAlgorithm::XChaCha20Poly1305);
+
+        // header-less blob, e.g. the wrapped key of a key slot written before
+        // schema version 6
+        let msg = aes.encrypt(b"some data", b"ad").unwrap();
+        let bare = [msg.nonce(), msg.ciphertext()].concat();
+        assert_eq!(
+            decrypt_blob(&bare, &aes, b"ad").unwrap().as_slice(),
+            b"some data"
+        );
+
+        // blob written with another cipher than the vault's, only read when
+        // re-encrypting
+        let encrypted = encrypt_blob(b"some data", &chacha, b"ad").unwrap();
+        assert!(matches!(
+            decrypt_blob(&encrypted, &aes, b"ad"),
+            Err(Error::CorruptBlob)
+        ));
+        assert_eq!(
+            decrypt_blob_with_any_algorithm(&encrypted, &aes, b"ad")
+                .unwrap()
+                .as_slice(),
+            b"some data"
+        );
+
+        // the header is authenticated
+        let encrypted = encrypt_blob(b"some data", &aes, b"ad").unwrap();
+        let mut tampered = encrypted.clone();
+        tampered[5] = CipherAlgorithm::XChaCha20Poly1305.id() as u8;
+        assert!(matches!(
+            decrypt_blob_with_any_algorithm(&tampered, &aes, b"ad"),
+            Err(Error::CorruptBlob)
+        ));
+    }
+
+    #[test]
+    fn create_then_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = test_db_path(dir.path());
+        let kdf = test_kdf();
+        Database::create(&path, &test_credentials(), kdf.clone(), "vault").unwrap();
+
+        let db = Database::open(&path, &test_credentials()).unwrap();
+        let groups = db.groups(None).unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].data().name(), "vault");
+        assert!(db.entries(None, None).unwrap().is_empty());
+        assert!(!db.is_legacy());
+        assert_eq!(db.kdf(), Some(&kdf));
+        assert_eq!(db.key_slots().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn open_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = test_db_path(dir.path());
+
+        let result = Database::open(&path, &test_credentials());
+        assert!(matches!(result, Err(Error::VaultNotFound(_))));
+
+        VaultBuilder::new().build(&path).unwrap();
+        let result = Database::create(&path, &test_credentials(), test_kdf(), "root");
+        assert!(matches!(result, Err(Error::VaultAlreadyExists(_))));
+
+        let wrong_credentials = Credentials::new("user".into(), "wrong".into());
+        let result = Database::open(&path, &wrong_credentials);
+        assert!(matches!(result, Err(Error::WrongCredentials)));
+
+        let db = Database::open(&path, &test_credentials()).unwrap();
+        assert!(matches!(db.entry(1000), Err(Error::EntryNotFound(1000))));
+    }
+
+    #[test]
+    fn failed_create_leaves_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = test_db_path(dir.path());
+
+        let invalid_kdf = KdfParams::with_random_salt(1, 1, 1);
+        let result = Database::create(&path, &test_credentials(), invalid_kdf, "root");
+        assert!(result.is_err());
+        assert!(!path.exists());
+
+        Database::create(&path, &test_credentials(), test_kdf(), "root").unwrap();
+        Database::open(&path, &test_credentials()).unwrap();
+    }
+
+    #[test]
+    fn open_and_upgrade_legacy_vault() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = test_db_path(dir.path());
+        let credentials = test_credentials();
+        let legacy_cipher = Cipher::new(&credentials.legacy_key());
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "
+CREATE TABLE Metadata (challenge BLOB NOT NULL);
+CREATE TABLE EntryGroup (id INTEGER PRIMARY KEY AUTOINCREMENT, data BLOB NOT NULL);
+CREATE TABLE Entry (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    group_id INTEGER NOT NULL REFERENCES EntryGroup(id),
+    data BLOB NOT NULL
+);
+CREATE TABLE EntryGroupParent (
+    parent_id INTEGER NOT NULL REFERENCES EntryGroup(id),
+    child_id INTEGER NOT NULL REFERENCES EntryGroup(id),
+    PRIMARY KEY(parent_id, child_id)
+);",
+        )
+        .unwrap();
+        // header-less, as written before schema version 6
+        let msg = legacy_cipher.encrypt(Database::CHALLENGE, &[]).unwrap();
+        conn.execute(
+            "INSERT INTO Metadata(challenge) VALUES (?1);",
+            [[msg.nonce(), msg.ciphertext()].concat()],
+        )
+        .unwrap();
+        drop(conn);
+
+        let mut db = Database::open(&path, &credentials).unwrap();
+        assert!(db.is_legacy());
+        assert_eq!(
+            migration::schema_version(&db.conn).unwrap(),
+            migration::SCHEMA_VERSION
+        );
+        assert!(dir.path().join("test.sqlite.v0.bak").exists());
+        assert!(db.vault_id.is_some());
+        db.insert_root_entry_group(&EntryGroupData::new("root".into()))
+            .unwrap();
+        db.insert_entry(
+            db.root_group_id(),
+            &EntryData::new("name".into(), "username".into(), "secret".into()),
+        )
+        .unwrap();
+
+        db.upgrade(&credentials, test_kdf()).unwrap();
+        drop(db);
+
+        let db = Database::open(&path, &credentials).unwrap();
+        assert!(!db.is_legacy());
+        let entries = db.entries(None, None).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].data().password(), "secret");
+    }
+
+    #[test]
+    fn swapped_blobs_fail_to_decrypt() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = test_db_path(dir.path());
+        let db = VaultBuilder::new()
+            .entry("", "first", "username", "secret")
+            .entry("", "second", "username", "secret")
+            .build(&path)
+            .unwrap();
+        let other_db = VaultBuilder::new()
+            .entry("", "first", "username", "secret")
+            .build(dir.path().join("other.sqlite"))
+            .unwrap();
+        let blob = |conn: &Connection, table: &str, id: i64| -> Vec<u8> {
+            let query = format!("SELECT data FROM {} WHERE id = ?1;", table);
+            conn.query_row(query.as_str(), [id], |row| row.get(0))
+                .unwrap()
+        };
+        let first_blob = blob(&db.conn, "Entry", 1);
+        let second_blob = blob(&db.conn, "Entry", 2);
+
+        let set_blob = |table: &str, id: i64, data: &[u8]| {
+            let update = format!("UPDATE {} SET data = ?1 WHERE id = ?2;", table);
+            db.conn
+                .execute(update.as_str(), rusqlite::params![data, id])
+                .unwrap();
+        };
+        set_blob("Entry", 1, &second_blob);
+        set_blob("Entry", 2, &first_blob);
+        assert!(matches!(db.entry(1), Err(Error::CorruptBlob)));
+        assert!(matches!(db.entry(2), Err(Error::CorruptBlob)));
+
+        set_blob("Entry", 1, &first_blob);
+        assert_eq!(db.entry(1).unwrap().data().name(), "first");
+        set_blob("EntryGroup", db.root_group_id(), &first_blob);
+        assert!(matches!(
+            db.entry_group(db.root_group_id()),
+            Err(Error::CorruptBlob)
+        ));
+
+        // same row in another vault, with the same key
+        other_db
+            .conn
+            .execute(
+                "UPDATE Entry SET data = ?1 WHERE id = 1;",
+                [blob(&db.conn, "Entry", 1)],
+            )
+            .unwrap();
+        let other_db = Database {
+            cipher: Cipher::new(&CipherKey::new(*db.data_key.as_ref())),
+            ..other_db
+        };
+        assert!(matches!(other_db.entry(1), Err(Error::CorruptBlob)));
+    }
+
+    #[test]
+    fn unlock_skips_slots_failing_the_challenge() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = test_db_path(dir.path());
+        let db = VaultBuilder::new().build(&path).unwrap();
+        let second_slot_id = db
+            .add_key_slot(KeySlotKind::Password, "second", b"password", &test_kdf())
+            .unwrap();
+
+        // the first slot unwraps another data key with the same password
+        let slot = db.unlocked_slot.as_ref().unwrap();
+        let wrapped_key = wrap_data_key(
+            &CipherKey::generate(),
+            &slot.cipher,
+            slot.id,
+            db.vault_id.as_ref(),
+        )
+        .unwrap();
+        db.conn
+            .execute(
+                "UPDATE KeySlot SET wrapped_key = ?1 WHERE id = ?2;",
+                rusqlite::params![wrapped_key, slot.id],
+            )
+            .unwrap();
+        drop(db);
+
+        let db = Database::open(&path, &test_credentials()).unwrap();
+        assert_eq!(db.unlocked_slot_id(), Some(second_slot_id));
+    }
+
+    #[test]
+    fn wrapped_keys_are_bound_to_their_slot() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = test_db_path(dir.path());
+        let db = VaultBuilder::new().build(&path).unwrap();
+        let slot_id = db.unlocked_slot_id().unwrap();
+        unbind_unlocked_slot(&db);
+        migration::set_schema_version(&db.conn, 7).unwrap();
+        drop(db);
+
+        // rewrapped once unlocked
+        let db = Database::open(&path, &test_credentials()).unwrap();
+        let (wrapped_key, bound): (Vec<u8>, bool) = db
+            .conn
+            .query_row(
+                "SELECT wrapped_key, wrapped_key_bound FROM KeySlot WHERE id = ?1;",
+                [slot_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert!(bound);
+        let slot_cipher = &db.unlocked_slot.as_ref().unwrap().cipher;
+        let associated_data = db.associated_data("KeySlot", slot_id);
+        unwrap_data_key(&wrapped_key, slot_cipher, slot_id, &associated_data).unwrap();
+        unwrap_data_key(&wrapped_key, slot_cipher, slot_id, &[]).unwrap_err();
+        // the header names the slot
+        unwrap_data_key(&wrapped_key, slot_cipher, slot_id + 1, &associated_data).unwrap_err();
+        decrypt_blob(&wrapped_key, slot_cipher, &associated_data).unwrap_err();
+        drop(db);
+
+        // the same slot under another id
+        let conn = Connection::open(&path).unwrap();
+        conn.execute(
+            "
+INSERT INTO KeySlot(
+    kind, label, kdf_salt, kdf_memory, kdf_iterations, kdf_parallelism, wrapped_key,
+    wrapped_key_bound
+)
+SELECT kind, label, kdf_salt, kdf_memory, kdf_iterations, kdf_parallelism, wrapped_key,
+    wrapped_key_bound
+FROM KeySlot
+WHERE id = ?1;",
+            [slot_id],
+        )
+        .unwrap();
+        conn.execute("DELETE FROM KeySlot WHERE id = ?1;", [slot_id])
+            .unwrap();
+        drop(conn);
+        let result = Database::open(&path, &test_credentials());
+        assert!(matches!(result, Err(Error::WrongCredentials)));
+    }
+
+    #[test]
+    fn change_cipher_algorithm() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = test_db_path(dir.path());
+        let mut db = VaultBuilder::new()
+            .entry("", "name", "username", "secret")
+            .build(&path)
+            .unwrap();
+        let blob = |conn: &Connection| -> Vec<u8> {
+            conn.query_row("SELECT data FROM Entry WHERE id = 1;", [], |row| row.get(0))
+                .unwrap()
+        };
+        let aes_blob = blob(&db.conn);
+        db.set_cipher_algorithm(CipherAlgorithm::XChaCha20Poly1305)
+            .unwrap();
+        assert_eq!(
+            blob(&db.conn).len(),
+            aes_blob.len() + CipherAlgorithm::XChaCha20Poly1305.nonce_size()
+                - CipherAlgorithm::Aes256Gcm.nonce_size()
+        );
+        drop(db);
+
+        let mut db = Database::open(&path, &test_credentials()).unwrap();
+        assert_eq!(db.cipher_algorithm(), CipherAlgorithm::XChaCha20Poly1305);
+        assert_eq!(db.entry(1).unwrap().data().password(), "secret");
+        db.rekey(CipherKey::generate(), false).unwrap();
+        db.set_cipher_algorithm(CipherAlgorithm::Aes256Gcm).unwrap();
+        drop(db);
+
+        let db = Database::open(&path, &test_credentials()).unwrap();
+        assert_eq!(db.cipher_algorithm(), CipherAlgorithm::Aes256Gcm);
+        assert_eq!(db.entry(1).unwrap().data().password(), "secret");
+    }
+
+    #[test]
+    fn migration_adds_blob_headers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = test_db_path(dir.path());
+        let db = VaultBuilder::new()
+            .entry("Work", "name", "username", "secret")
+            .build(&path)
+            .unwrap();
+        // header-less blobs, as written before schema version 6
+        let tables = [
+            ("Metadata", "challenge"),
+            ("EntryGroup", "data"),
+            ("Entry", "data"),
+        ];
+        for (table, column) in tables {
+            let row_ids: Vec<i64> = db
+                .conn
+                .prepare(&format!("SELECT rowid FROM {};", table))
+                .unwrap()
+                .query_map([], |row| row.get(0))
+                .unwrap()
+                .collect::<rusqlite::Result<_>>()
+                .unwrap();
+            for row_id in row_ids {
+                let select = format!("SELECT {} FROM {} WHERE rowid = ?1;", column, table);
+                let blob: Vec<u8> = db
+                    .conn
+                    .query_row(&select, [row_id], |row| row.get(0))
+                    .unwrap();
+                let associated_data = db.associated_data(table, row_id);
+                let data = decrypt_blob(&blob, &db.cipher, &associated_data).unwrap();
+                let msg = db.cipher.encrypt(&data, &associated_data).unwrap();
+                let update = format!("UPDATE {} SET {} = ?1 WHERE rowid = ?2;", table, column);
+                db.conn
+                    .execute(
+                        &update,
+                        rusqlite::params![[msg.nonce(), msg.ciphertext()].concat(), row_id],
+                    )
+                    .unwrap();
+            }
+        }
+        unbind_unlocked_slot(&db);
+        migration::set_schema_version(&db.conn, 5).unwrap();
+        drop(db);
+
+        let db = Database::open(&path, &test_credentials()).unwrap();
+        for (table, column) in tables {
+            let mut stmt = db
+                .conn
+                .prepare(&format!("SELECT {} FROM {};", column, table))
+                .unwrap();
+            let blobs = stmt.query_map([], |row| row.get::<_, Vec<u8>>(0)).unwrap();
+            for blob in blobs {
+                assert!(BlobHeader::parse(&blob.unwrap()).is_some());
+            }
+        }
+        let entries = db.entries(None, None).unwrap();
+        assert_eq!(entries[0].data().password(), "secret");
+    }
+
+    #[test]
+    fn reject_newer_schema() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = test_db_path(dir.path());
+        let db = VaultBuilder::new().build(&path).unwrap();
+        migration::set_schema_version(&db.conn, migration::SCHEMA_VERSION + 1).unwrap();
+        drop(db);
+
+        let result = Database::open(&path, &test_credentials());
+        assert!(matches!(result, Err(Error::UnsupportedSchemaVersion(_))));
+    }
+
+    #[test]
+    fn change_password() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = test_db_path(dir.path());
+        let mut db = VaultBuilder::new().build(&path).unwrap();
+        let entry_id = db
+            .insert_entry(
+                db.root_group_id(),
+                &EntryData::new("name".into(), "username".into(), "secret".into()),
+            )
+            .unwrap();
+
+        let new_credentials = Credentials::new("user".into(), "new password".into());
+        db.change_password(&new_credentials, test_kdf()).unwrap();
+        drop(db);
+
+        let db = Database::open(&path, &new_credentials).unwrap();
+        assert_eq!(db.entry(entry_id).unwrap().data().password(), "secret");
+    }
+
+    #[test]
+    fn key_file_slot() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = test_db_path(dir.path());
+        let db = VaultBuilder::new().build(&path).unwrap();
+        let slot_id = db
+            .add_key_slot(KeySlotKind::KeyFile, "key file", b"key file", &test_kdf())
+            .unwrap();
+        drop(db);
+
+        let db = Database::open_with_key_file(&path, b"key file").unwrap();
+        assert_eq!(db.unlocked_slot_id(), Some(slot_id));
+
+        db.remove_key_slot(slot_id).unwrap();
+        let password_slot_id = db.key_slots().unwrap()[0].id();
+        db.remove_key_slot(password_slot_id).unwrap_err();
+    }
+
+    #[test]
+    fn key_file_second_factor() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = test_db_path(dir.path());
+        let credentials = test_credentials().with_key_file(b"key file");
+        let db = Database::create(&path, &credentials, test_kdf(), "root").unwrap();
+        assert!(db.requires_key_file().unwrap());
+        db.add_key_slot(
+            KeySlotKind::Password,
+            "second",
+            b"second password",
+            &test_kdf(),
+        )
+        .unwrap();
+        drop(db);
+
+        let result = Database::open(&path, &test_credentials());
+        assert!(matches!(result, Err(Error::KeyFileRequired)));
+        let wrong_key_file = test_credentials().with_key_file(b"other key file");
+        let result = Database::open(&path, &wrong_key_file);
+        assert!(matches!(result, Err(Error::WrongCredentials)));
+        Database::open(&path, &credentials).unwrap();
+        let second =
+            Credentials::new("user".into(), "second password".into()).with_key_file(b"key file");
+        Database::open(&path, &second).unwrap();
+
+        let other_path = dir.path().join("other.sqlite");
+        VaultBuilder::new().build(&other_path).unwrap();
+        let result = Database::open(&other_path, &credentials);
+        assert!(matches!(result, Err(Error::InvalidInput(_))));
+    }
+
+    #[test]
+    fn reset_master_password_with_recovery_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = test_db_path(dir.path());
+        let db = VaultBuilder::new()
+            .entry("", "name", "username", "secret")
+            .build(&path)
+            .unwrap();
+        let old_recovery_key = db.add_recovery_key(&test_kdf()).unwrap();
+        let recovery_key = db.add_recovery_key(&test_kdf()).unwrap();
+        assert_eq!(db.key_slots().unwrap().len(), 2);
+        drop(db);
+
+        let result = Database::open_with_recovery_key(&path, &old_recovery_key);
+        assert!(matches!(result, Err(Error::WrongCredentials)));
+        let mut db = Database::open_with_recovery_key(&path, &recovery_key).unwrap();
+        let new_credentials = Credentials::new("user".into(), "new password".into());
+        db.reset_master_password(&new_credentials, test_kdf())
+            .unwrap();
+        drop(db);
+
+        let result = Database::open(&path, &test_credentials());
+        assert!(matches!(result, Err(Error::WrongCredentials)));
+        let db = Database::open(&path, &new_credentials).unwrap();
+        assert_eq!(db.entry(1).unwrap().data().password(), "secret");
+        assert_eq!(db.key_slots().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn reset_master_password_with_recovery_shares() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = test_db_path(dir.path());
+        let db = VaultBuilder::new()
+            .entry("", "name", "username", "secret")
+            .build(&path)
+            .unwrap();
+        let mut shares = db.add_recovery_shares(&test_kdf(), 2, 3).unwrap();
+        drop(db);
+
+        shares.remove(0);
+        let mut db = Database::open_with_recovery_shares(&path, shares).unwrap();
+        let new_credentials = Credentials::new("user".into(), "new password".into());
+        db.reset_master_password(&new_credentials, test_kdf())
+            .unwrap();
+        let shares = db.add_recovery_shares(&test_kdf(), 2, 2).unwrap();
+        assert_eq!(db.key_slots().unwrap().len(), 2);
+        drop(db);
+
+        let db = Database::open(&path, &new_credentials).unwrap();
+        assert_eq!(db.entry(1).unwrap().data().password(), "secret");
+        drop(db);
+        let result =
+            Database::open_with_recovery_shares(&path, shares.into_iter().take(1).collect());
+        assert!(matches!(result, Err(Error::InvalidInput(_))));
+    }
+
+    #[test]
+    fn failed_rekey_leaves_vault_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = test_db_path(dir.path());
+        let mut db = VaultBuilder::new().build(&path).unwrap();
+        let entry_id = db
+            .insert_entry(
+                db.root_group_id(),
+                &EntryData::new("name".into(), "username".into(), "secret".into()),
+            )
+            .unwrap();
+        db.conn
+            .execute(
+                "INSERT INTO Entry(group_id, data) VALUES (?1, ?2);",
+                rusqlite::params![db.root_group_id(), b"corrupted blob".as_slice()],
+            )
+            .unwrap();
+
+        db.rekey(CipherKey::generate(), false).unwrap_err();
+        drop(db);
+
+        let db = Database::open(&path, &test_credentials()).unwrap();
+        assert_eq!(db.entry(entry_id).unwrap().data().password(), "secret");
+    }
+
+    #[test]
+    fn rekey_with_other_key_slots() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = test_db_path(dir.path());
+        let mut db = VaultBuilder::new().build(&path).unwrap();
+        let entry_id = db
+            .insert_entry(
+                db.root_group_id(),
+                &EntryData::new("name".into(), "username".into(), "secret".into()),
+            )
+            .unwrap();
+        let slot_id = db
+            .add_key_slot(KeySlotKind::KeyFile, "key file", b"key file", &test_kdf())
+            .unwrap();
+
+        let result = db.rekey(CipherKey::generate(), false);
+        assert!(matches!(result, Err(Error::InvalidInput(_))));
+        drop(db);
+        let db = Database::open_with_key_file(&path, b"key file").unwrap();
+        assert_eq!(db.key_slots().unwrap().len(), 2);
+        drop(db);
+
+        let mut db = Database::open(&path, &test_credentials()).unwrap();
+        let removed = db.rekey(CipherKey::generate(), true).unwrap();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].id(), slot_id);
+        drop(db);
+
+        let result = Database::open_with_key_file(&path, b"key file");
+        assert!(matches!(result, Err(Error::WrongCredentials)));
+        let db = Database::open(&path, &test_credentials()).unwrap();
+        assert_eq!(db.key_slots().unwrap().len(), 1);
+        assert_eq!(db.entry(entry_id).unwrap().data().password(), "secret");
+    }
+
+    #[test]
+    fn update_move_and_delete_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = test_db_path(dir.path());
+        let mut db = VaultBuilder::new().build(&path).unwrap();
+        let entry_id = db
+            .insert_entry(
+                db.root_group_id(),
+                &EntryData::new("name".into(), "username".into(), "secret".into()),
+            )
+            .unwrap();
+        let group_id = db
+            .insert_entry_group(db.root_group_id(), &EntryGroupData::new("group".into()))
+            .unwrap();
+
+        let mut entry_data = db.entry(entry_id).unwrap().data().clone();
+        entry_data.set_name("new name".into());
+        entry_data.set_password("new secret".into());
+        db.update_entry(entry_id, &entry_data).unwrap();
+        let updated = db.entry(entry_id).unwrap();
+        assert_eq!(updated.data().name(), "new name");
+        assert_eq!(updated.data().username(), "username");
+        assert_eq!(updated.data().password(), "new secret");
+
+        db.move_entry(entry_id, i64::MAX).unwrap_err();
+        db.move_entry(entry_id, group_id).unwrap();
+        let in_group = db.entries(None, Some(group_id)).unwrap();
+        assert_eq!(in_group.len(), 1);
+        assert_eq!(in_group[0].id(), entry_id);
+
+        db.delete_entry(entry_id).unwrap();
+        db.entry(entry_id).unwrap_err();
+        db.delete_entry(entry_id).unwrap_err();
+    }
+
+    #[test]
+    fn hotp_counter_is_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = test_db_path(dir.path());
+        let db = VaultBuilder::new().build(&path).unwrap();
+        let mut entry_data = EntryData::new("name".into(), "username".into(), "secret".into());
+        let entry_id = db.insert_entry(db.root_group_id(), &entry_data).unwrap();
+        db.next_hotp_code(entry_id).unwrap_err();
+
+        // RFC 4226 test secret
+        let uri = "otpauth://hotp/name?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&counter=1";
+        entry_data.set_otp(Some(Otp::parse_uri(uri).unwrap()));
+        db.update_entry(entry_id, &entry_data).unwrap();
+        assert_eq!(db.next_hotp_code(entry_id).unwrap().expose(), "287082");
+        assert_eq!(db.next_hotp_code(entry_id).unwrap().expose(), "359152");
+        let entry = db.entry(entry_id).unwrap();
+        assert_eq!(
+            entry.data().otp().unwrap().kind(),
+            OtpKind::Hotp { counter: 3 }
+        );
+    }
+
+    #[test]
+    fn entry_timestamps() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = test_db_path(dir.path());
+        let db = VaultBuilder::new().build(&path).unwrap();
+        let before = timestamp::now();
+        let entry_id = db
+            .insert_entry(
+                db.root_group_id(),
+                &EntryData::new("name".into(), "username".into(), "secret".into()),
+            )
+            .unwrap();
+        let entry_data = db.entry(entry_id).unwrap().data().clone();
+        let created = entry_data.created().unwrap();
+        assert!(created >= before);
+        assert_eq!(entry_data.modified(), Some(created));
+        assert_eq!(entry_data.password_changed(), Some(created));
+        assert_eq!(entry_data.accessed(), None);
+
+        // as if written long ago
+        let mut old = entry_data.clone();
+        old.created = Some(1);
+        old.modified = Some(1);
+        old.password_changed = Some(1);
+        db.write_entry(entry_id, &old).unwrap();
+
+        let mut entry_data = db.entry(entry_id).unwrap().data().clone();
+        entry_data.set_username("new username".into());
+        // ignored, the database keeps track of them
+        entry_data.created = None;
+        db.update_entry(entry_id, &entry_data).unwrap();
+        let entry_data = db.entry(entry_id).unwrap().data().clone();
+        assert_eq!(entry_data.created(), Some(1));
+        assert!(entry_data.modified().unwrap() >= before);
+        assert_eq!(entry_data.password_changed(), Some(1));
+
+        let mut entry_data = entry_data.clone();
+        entry_data.set_password("new secret".into());
+        db.update_entry(entry_id, &entry_data).unwrap();
+        db.record_access(&[entry_id]).unwrap();
+        let entry_data = db.entry(entry_id).unwrap().data().clone();
+        assert!(entry_data.password_changed().unwrap() >= before);
+        assert!(entry_data.accessed().unwrap() >= before);
+        assert_eq!(entry_data.created(), Some(1));
+    }
+
+    #[test]
+    fn count_tags() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = test_db_path(dir.path());
+        let db = VaultBuilder::new().build(&path).unwrap();
+        assert!(db.tags().unwrap().is_empty());
+
+        for tags in [["prod", "shared"].as_slice(), &["prod"], &[]] {
+            let mut entry_data = EntryData::new("name".into(), "username".into(), "secret".into());
+            for tag in tags {
+                entry_data.add_tag((*tag).into());
+            }
+            db.insert_entry(db.root_group_id(), &entry_data).unwrap();
+        }
+        let tags = db.tags().unwrap();
+        assert_eq!(tags, [("prod".to_string(), 2), ("shared".to_string(), 1)]);
+    }
+
+    #[test]
+    fn attachments_are_chunked_and_deleted_with_their_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = test_db_path(dir.path());
+        let mut db = VaultBuilder::new().build(&path).unwrap();
+        let entry_data = EntryData::new("name".into(), "username".into(), "secret".into());
+        let entry_id = db.insert_entry(db.root_group_id(), &entry_data).unwrap();
+        let count = |db: &Database, table: &str| -> i64 {
+            let query = format!("SELECT COUNT(*) FROM {};", table);
+            db.conn.query_row(&query, [], |row| row.get(0)).unwrap()
+        };
+
+        // three chunks, the last one partial
+        let content: Vec<u8> = (0..150_000u32).map(|i| (i % 251) as u8).collect();
+        db.insert_attachment(entry_id, "key.pem", content.as_slice())
+            .unwrap();
+        db.insert_attachment(entry_id, "empty", [].as_slice())
+            .unwrap();
+        db.insert_attachment(entry_id, "key.pem", [].as_slice())
+            .unwrap_err();
+        assert_eq!(count(&db, "AttachmentChunk"), 4);
+        let names: Vec<String> = db
+            .attachments(entry_id)
+            .unwrap()
+            .iter()
+            .map(|attachment| attachment.name().to_string())
+            .collect();
+        assert_eq!(names, ["empty", "key.pem"]);
+
+        db.set_cipher_algorithm(CipherAlgorithm::XChaCha20Poly1305)
+            .unwrap();
+        let mut read = Vec::new();
+        let size = db.read_attachment(entry_id, "key.pem", &mut read).unwrap();
+        assert_eq!(size, content.len() as u64);
+        assert_eq!(read, content);
+        let mut read = Vec::new();
+        db.read_attachment(entry_id, "empty", &mut read).unwrap();
+        assert!(read.is_empty());
+
+        let too_large = std::io::repeat(0).take(Database::MAX_ATTACHMENT_SIZE + 1);
+        db.insert_attachment(entry_id, "large", too_large)
+            .unwrap_err();
+        assert_eq!(count(&db, "Attachment"), 2);
+        assert_eq!(count(&db, "AttachmentChunk"), 4);
+
+        // reordered chunks are detected
+        db.conn
+            .execute_batch(
+                "
+UPDATE AttachmentChunk SET position = -1 WHERE position = 0;
+UPDATE AttachmentChunk SET position = 0 WHERE position = 1;
+UPDATE AttachmentChunk SET position = 1 WHERE position = -1;",
+            )
+            .unwrap();
+        let err = db
+            .read_attachment(entry_id, "key.pem", std::io::sink())
+            .unwrap_err();
+        assert!(matches!(err, Error::CorruptBlob));
+
+        db.delete_attachment(entry_id, "empty").unwrap();
+        db.delete_attachment(entry_id, "empty").unwrap_err();
+        assert_eq!(count(&db, "AttachmentChunk"), 3);
+        db.delete_entry(entry_id).unwrap();
+        assert_eq!(count(&db, "Attachment"), 0);
+        assert_eq!(count(&db, "AttachmentChunk"), 0);
+    }
+
+    #[test]
+    fn move_and_delete_groups() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = test_db_path(dir.path());
+        let mut db = VaultBuilder::new().build(&path).unwrap();
+        let root_id = db.root_group_id();
+        let parent_id = db
+            .insert_entry_group(root_id, &EntryGroupData::new("parent".into()))
+            .unwrap();
+        let child_id = db
+            .insert_entry_group(parent_id, &EntryGroupData::new("child".into()))
+            .unwrap();
+        let entry_id = db
+            .insert_entry(
+                child_id,
+                &EntryData::new("name".into(), "username".into(), "secret".into()),
+            )
+            .unwrap();
+
+        db.update_entry_group(child_id, &EntryGroupData::new("renamed".into()))
+            .unwrap();
+        assert_eq!(db.entry_group(child_id).unwrap().data().name(), "renamed");
+
+        db.move_entry_group(parent_id, parent_id).unwrap_err();
+        db.move_entry_group(parent_id, child_id).unwrap_err();
+        db.move_entry_group(root_id, child_id).unwrap_err();
+
+        db.delete_entry_group(root_id, true).unwrap_err();
+        db.delete_entry_group(parent_id, false).unwrap_err();
+        db.delete_entry_group(parent_id, true).unwrap();
+        db.entry_group(child_id).unwrap_err();
+        db.entry(entry_id).unwrap_err();
+    }
+
+    #[test]
+    fn hide_and_show_structure() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = test_db_path(dir.path());
+        let mut db = VaultBuilder::new()
+            .hide_structure()
+            .entry("Work/AWS", "prod-root", "admin", "secret")
+            .entry("Personal", "email", "me", "hunter2")
+            .build(&path)
+            .unwrap();
+        assert!(db.hides_structure().unwrap());
+        let clear_parents: i64 = db
+            .conn
+            .query_row("SELECT COUNT(*) FROM EntryGroupParent;", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(clear_parents, 0);
+        let clear_group_ids: Vec<i64> = db
+            .conn
+            .prepare("SELECT DISTINCT group_id FROM Entry;")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(clear_group_ids, [db.root_group_id()]);
+
+        let aws_id = db.resolve_group(&ItemRef::Path("Work/AWS".into())).unwrap();
+        let personal_id = db.resolve_group(&ItemRef::Path("Personal".into())).unwrap();
+        let entry_id = db
+            .resolve_entry(&ItemRef::Path("Work/AWS/prod-root".into()))
+            .unwrap();
+        db.move_entry(entry_id, personal_id).unwrap();
+        db.move_entry_group(aws_id, personal_id).unwrap();
+        assert_eq!(db.group_path(aws_id).unwrap(), "Personal/AWS");
+        assert_eq!(db.entries(None, Some(personal_id)).unwrap().len(), 2);
+        db.delete_entry_group(personal_id, false).unwrap_err();
+
+        db.set_structure_hidden(false).unwrap();
+        assert!(!db.hides_structure().unwrap());
+        assert_eq!(db.groups(Some(personal_id)).unwrap()[0].id(), aws_id);
+        assert_eq!(db.entries(None, Some(personal_id)).unwrap().len(), 2);
+
+        db.set_structure_hidden(true).unwrap();
+        db.delete_entry_group(personal_id, true).unwrap();
+        assert_eq!(db.groups(None).unwrap().len(), 2);
+        assert!(db.entries(None, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn save_and_remove_policies() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = test_db_path(dir.path());
+        let db = VaultBuilder::new().build(&path).unwrap();
+        let mut policy = PasswordPolicy::default();
+        policy.set_length(16);
+        db.save_policy("bank", &policy).unwrap();
+        db.save_policy("default", &PasswordPolicy::default())
+            .unwrap();
+        policy.set_symbols("-".into());
+        db.save_policy("bank", &policy).unwrap();
+
+        let names: Vec<String> = db
+            .policies()
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, ["bank", "default"]);
+        assert_eq!(db.policy("bank").unwrap(), policy);
+
+        db.remove_policy("bank").unwrap();
+        assert!(matches!(db.policy("bank"), Err(Error::PolicyNotFound(_))));
+    }
+
+    #[test]
+    fn resolve_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = test_db_path(dir.path());
+        let mut db = VaultBuilder::new().build(&path).unwrap();
+        let root_id = db.root_group_id();
+        let work_id = db
+            .insert_entry_group(root_id, &EntryGroupData::new("Work".into()))
+            .unwrap();
+        let ci_id = db
+            .insert_entry_group(work_id, &EntryGroupData::new("CI/CD".into()))
+            .unwrap();
+        let entry_id = db
+            .insert_entry(
+                ci_id,
+                &EntryData::new("deploy".into(), "username".into(), "secret".into()),
+            )
+            .unwrap();
+
+        let resolve_group = |path: &str| db.resolve_group(&ItemRef::Path(path.into()));
+        assert_eq!(resolve_group("/").unwrap(), root_id);
+        assert_eq!(resolve_group(r"Work/CI\/CD").unwrap(), ci_id);
+        assert!(matches!(
+            resolve_group("Work/CI"),
+            Err(Error::PathNotFound(_))
+        ));
+        let entry_ref = ItemRef::Path(r"/Work/CI\/CD/deploy".into());
+        assert_eq!(db.resolve_entry(&entry_ref).unwrap(), entry_id);
+        assert_eq!(db.group_path(ci_id).unwrap(), r"Work/CI\/CD");
+
+        db.insert_entry_group(root_id, &EntryGroupData::new("Work".into()))
+            .unwrap();
+        let result = db.resolve_entry(&entry_ref);
+        assert!(matches!(result, Err(Error::AmbiguousPath(_))));
+    }
+}
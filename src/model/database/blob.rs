@@ -0,0 +1,100 @@
+//! Header of encrypted blobs, describing how the payload following it was
+//! encrypted. Blobs written before the header existed are a bare
+//! `nonce || ciphertext`: they get a header when migrating to schema version
+//! 6, except the wrapped keys of key slots which are read without one until
+//! their slot unlocks the vault.
+
+use crate::encryption::CipherAlgorithm;
+
+/// Starts every blob with a header. A blob starting with it is only read as
+/// having a header: a header-less blob whose random nonce happened to start
+/// like a readable header, a chance of about 2^-40, would fail to decrypt.
+const MAGIC: [u8; 4] = *b"\x89PCB";
+/// Version of the header and payload layout written by this release
+const FORMAT_VERSION: u8 = 1;
+/// No flag is defined yet, blobs with any flag set are not read
+const KNOWN_FLAGS: u8 = 0;
+
+/// Key slot id of blobs encrypted with the vault data key
+pub(super) const DATA_KEY_SLOT_ID: u32 = 0;
+
+/// `magic || version || cipher id || key slot id (u32 LE) || flags`, all of
+/// it authenticated along with the blob's associated data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) struct BlobHeader {
+    pub(super) cipher: CipherAlgorithm,
+    /// Slot whose key encrypted the blob, [`DATA_KEY_SLOT_ID`] for the data
+    /// key
+    pub(super) key_slot_id: u32,
+    pub(super) flags: u8,
+}
+
+impl BlobHeader {
+    pub(super) const SIZE: usize = MAGIC.len() + 1 + 1 + 4 + 1;
+
+    pub(super) fn new(cipher: CipherAlgorithm, key_slot_id: u32) -> Self {
+        Self {
+            cipher,
+            key_slot_id,
+            flags: 0,
+        }
+    }
+
+    pub(super) fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0u8; Self::SIZE];
+        bytes[..4].copy_from_slice(&MAGIC);
+        bytes[4] = FORMAT_VERSION;
+        bytes[5] = self.cipher.id() as u8;
+        bytes[6..10].copy_from_slice(&self.key_slot_id.to_le_bytes());
+        bytes[10] = self.flags;
+        return bytes;
+    }
+
+    /// Split the header off `blob`. `None` if the blob does not start with a
+    /// header this release can read, e.g. a header-less blob.
+    pub(super) fn parse(blob: &[u8]) -> Option<(Self, &[u8])> {
+        if blob.len() < Self::SIZE || blob[..4] != MAGIC || blob[4] != FORMAT_VERSION {
+            return None;
+        }
+        let cipher = CipherAlgorithm::from_id(blob[5] as i64)?;
+        let key_slot_id = u32::from_le_bytes(blob[6..10].try_into().ok()?);
+        let flags = blob[10];
+        if flags & !KNOWN_FLAGS != 0 {
+            return None;
+        }
+
+        let header = Self {
+            cipher,
+            key_slot_id,
+            flags,
+        };
+        return Some((header, &blob[Self::SIZE..]));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BlobHeader, DATA_KEY_SLOT_ID};
+    use crate::encryption::CipherAlgorithm;
+
+    #[test]
+    fn parse_header() {
+        let header = BlobHeader::new(CipherAlgorithm::XChaCha20Poly1305, 7);
+        let mut blob = header.to_bytes().to_vec();
+        blob.extend_from_slice(b"payload");
+        assert_eq!(
+            BlobHeader::parse(&blob),
+            Some((header, b"payload".as_slice()))
+        );
+
+        let mut unknown_version = blob.clone();
+        unknown_version[4] = 2;
+        assert_eq!(BlobHeader::parse(&unknown_version), None);
+        let mut unknown_flag = blob.clone();
+        unknown_flag[10] = 1;
+        assert_eq!(BlobHeader::parse(&unknown_flag), None);
+        assert_eq!(BlobHeader::parse(&[0u8; 40]), None);
+        let short = BlobHeader::new(CipherAlgorithm::Aes256Gcm, DATA_KEY_SLOT_ID).to_bytes();
+        assert!(BlobHeader::parse(&short[..BlobHeader::SIZE - 1]).is_none());
+    }
+}
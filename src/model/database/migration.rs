@@ -0,0 +1,262 @@
+//! Schema versioning of vaults. The version is stored in SQLite's
+//! `user_version` header field, 0 meaning a vault created before versioning.
+//! Older vaults are migrated when opened, after a backup copy is written next
+//! to them.
+
+use std::path::PathBuf;
+
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use rusqlite::{Connection, Transaction};
+
+use crate::{encryption::Cipher, Error, Result};
+
+use super::{read_vault_id, reencrypt_blobs, Database, VaultId};
+
+/// Version of the schema created by [`SCHEMA`], and of migrated vaults
+pub(super) const SCHEMA_VERSION: u32 = 8;
+/// Schema of new vaults
+pub(super) const SCHEMA: &str = include_str!("../../../sql/create.sql");
+
+struct Migration {
+    /// Schema version reached by this migration
+    version: u32,
+    description: &'static str,
+    /// Run in the migration transaction, with the unlocked vault's data
+    /// cipher so that encrypted data can be migrated too
+    apply: fn(&Transaction<'_>, &Cipher) -> Result<()>,
+}
+
+/// Every migration, ordered by version
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "add the PasswordPolicy table",
+        apply: add_password_policy_table,
+    },
+    Migration {
+        version: 2,
+        description: "bind encrypted blobs to their vault, table and row",
+        apply: bind_blobs_to_rows,
+    },
+    Migration {
+        version: 3,
+        description: "add the StructureIndex table",
+        apply: add_structure_index_table,
+    },
+    Migration {
+        version: 4,
+        description: "record whether a key file is required",
+        apply: add_requires_key_file_column,
+    },
+    Migration {
+        version: 5,
+        description: "record the cipher of the vault's data",
+        apply: add_cipher_column,
+    },
+    Migration {
+        version: 6,
+        description: "write encrypted blobs with a header",
+        apply: add_blob_headers,
+    },
+    Migration {
+        version: 7,
+        description: "add the Attachment and AttachmentChunk tables",
+        apply: add_attachment_tables,
+    },
+    Migration {
+        version: 8,
+        description: "bind wrapped keys to their vault and key slot",
+        apply: add_wrapped_key_bound_column,
+    },
+];
+
+pub(super) fn schema_version(conn: &Connection) -> Result<u32> {
+    let version = conn.query_row("PRAGMA user_version;", [], |row| row.get(0))?;
+    return Ok(version);
+}
+
+pub(super) fn set_schema_version(conn: &Connection, version: u32) -> Result<()> {
+    conn.pragma_update(None, "user_version", version)?;
+    return Ok(());
+}
+
+/// Fail on vaults created by a newer release, which this one cannot read.
+pub(super) fn check_schema_version(conn: &Connection) -> Result<()> {
+    let version = schema_version(conn)?;
+    if version > SCHEMA_VERSION {
+        return Err(Error::UnsupportedSchemaVersion(version));
+    }
+
+    return Ok(());
+}
+
+/// Back up the vault, then run all pending migrations in a single
+/// transaction.
+pub(super) fn migrate(db: &mut Database) -> Result<()> {
+    let version = schema_version(&db.conn)?;
+    if version == SCHEMA_VERSION {
+        return Ok(());
+    }
+    if version > SCHEMA_VERSION {
+        return Err(Error::UnsupportedSchemaVersion(version));
+    }
+
+    let backup_path = backup(&db.conn, version)?;
+    log::info!(
+        "backed up the vault to {} before migrating it",
+        backup_path.display()
+    );
+
+    let tx = db.conn.transaction()?;
+    for migration in MIGRATIONS.iter().filter(|m| m.version > version) {
+        log::info!(
+            "migrating the vault to schema version {}: {}",
+            migration.version,
+            migration.description
+        );
+        (migration.apply)(&tx, &db.cipher)?;
+    }
+    set_schema_version(&tx, SCHEMA_VERSION)?;
+    tx.commit()?;
+
+    return Ok(());
+}
+
+/// Write a consistent copy of the vault next to it, e.g.
+/// `bob.sqlite.v0.bak`, without overwriting previous backups.
+fn backup(conn: &Connection, version: u32) -> Result<PathBuf> {
+    let path = conn
+        .path()
+        .filter(|path| !path.is_empty())
+        .ok_or_else(|| Error::InvalidInput("cannot back up an in-memory vault".into()))?;
+
+    let mut backup_path = PathBuf::from(format!("{}.v{}.bak", path, version));
+    let mut copy = 1;
+    while backup_path.exists() {
+        backup_path = PathBuf::from(format!("{}.v{}.bak.{}", path, version, copy));
+        copy += 1;
+    }
+
+    let backup_path_str = backup_path
+        .to_str()
+        .ok_or_else(|| Error::InvalidInput("invalid backup path".into()))?;
+    conn.execute("VACUUM INTO ?1;", [backup_path_str])?;
+
+    return Ok(backup_path);
+}
+
+/// Vaults created before password policies were added lack the table
+fn add_password_policy_table(tx: &Transaction<'_>, _: &Cipher) -> Result<()> {
+    tx.execute_batch(
+        "
+CREATE TABLE IF NOT EXISTS PasswordPolicy (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    data BLOB NOT NULL
+);",
+    )?;
+    return Ok(());
+}
+
+/// Give the vault a random id and re-encrypt every blob with its vault id,
+/// table and row id as associated data, see [`super::associated_data`].
+fn bind_blobs_to_rows(tx: &Transaction<'_>, cipher: &Cipher) -> Result<()> {
+    let mut vault_id = VaultId::default();
+    OsRng.fill_bytes(&mut vault_id);
+
+    tx.execute_batch("ALTER TABLE Metadata ADD COLUMN vault_id BLOB;")?;
+    tx.execute("UPDATE Metadata SET vault_id = ?1;", [vault_id.as_slice()])?;
+    // encrypted columns as of this version, later tables do not exist yet
+    let columns = [
+        ("Metadata", "challenge"),
+        ("EntryGroup", "data"),
+        ("Entry", "data"),
+        ("PasswordPolicy", "data"),
+    ];
+    reencrypt_blobs(tx, &columns, (cipher, None), (cipher, Some(&vault_id)))?;
+
+    return Ok(());
+}
+
+/// Vaults created before the structure could be hidden lack the table
+fn add_structure_index_table(tx: &Transaction<'_>, _: &Cipher) -> Result<()> {
+    tx.execute_batch(
+        "
+CREATE TABLE IF NOT EXISTS StructureIndex (
+    id INTEGER PRIMARY KEY,
+    data BLOB NOT NULL
+);",
+    )?;
+    return Ok(());
+}
+
+/// Vaults created before key files could be required do not require one
+fn add_requires_key_file_column(tx: &Transaction<'_>, _: &Cipher) -> Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE Metadata ADD COLUMN requires_key_file INTEGER NOT NULL DEFAULT 0;",
+    )?;
+    return Ok(());
+}
+
+/// Vaults so far were all encrypted with AES-256-GCM, whose id is 0
+fn add_cipher_column(tx: &Transaction<'_>, _: &Cipher) -> Result<()> {
+    tx.execute_batch("ALTER TABLE Metadata ADD COLUMN cipher INTEGER NOT NULL DEFAULT 0;")?;
+    return Ok(());
+}
+
+/// Re-encrypt every blob with a header, see [`super::blob`]. Wrapped keys of
+/// key slots cannot be rewrapped without their secrets, they get a header
+/// once their slot unlocks the vault.
+fn add_blob_headers(tx: &Transaction<'_>, cipher: &Cipher) -> Result<()> {
+    let vault_id = read_vault_id(tx)?;
+    // encrypted columns as of this version, later tables do not exist yet
+    let columns = [
+        ("Metadata", "challenge"),
+        ("EntryGroup", "data"),
+        ("Entry", "data"),
+        ("PasswordPolicy", "data"),
+        ("StructureIndex", "data"),
+    ];
+    reencrypt_blobs(
+        tx,
+        &columns,
+        (cipher, vault_id.as_ref()),
+        (cipher, vault_id.as_ref()),
+    )?;
+
+    return Ok(());
+}
+
+/// Vaults created before attachments lack their tables
+fn add_attachment_tables(tx: &Transaction<'_>, _: &Cipher) -> Result<()> {
+    tx.execute_batch(
+        "
+CREATE TABLE IF NOT EXISTS Attachment (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    entry_id INTEGER NOT NULL REFERENCES Entry(id),
+    data BLOB NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS AttachmentChunk (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    attachment_id INTEGER NOT NULL REFERENCES Attachment(id),
+    position INTEGER NOT NULL,
+    data BLOB NOT NULL,
+
+    UNIQUE(attachment_id, position)
+);",
+    )?;
+    return Ok(());
+}
+
+/// Wrapped keys cannot be rewrapped without the secrets of their key slots:
+/// they are marked as unbound, and rewrapped when their slot unlocks the
+/// vault. Legacy vaults have no key slots yet.
+fn add_wrapped_key_bound_column(tx: &Transaction<'_>, _: &Cipher) -> Result<()> {
+    if !Database::has_key_slots(tx)? {
+        return Ok(());
+    }
+    tx.execute_batch(
+        "ALTER TABLE KeySlot ADD COLUMN wrapped_key_bound INTEGER NOT NULL DEFAULT 0;",
+    )?;
+    return Ok(());
+}
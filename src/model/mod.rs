@@ -1,23 +1,75 @@
+use std::collections::BTreeSet;
+
 use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
 
-use crate::encryption::CipherKey;
+use crate::{
+    encryption::{CipherKey, KdfError, KdfParams, KeyFileHash},
+    otp::Otp,
+    secret::SecretString,
+};
 
 pub mod database;
+pub mod path;
 
 pub struct Credentials {
     user: Box<str>,
-    password: Box<str>,
+    password: SecretString,
+    /// Second factor, for vaults that require a key file
+    key_file: Option<KeyFileHash>,
 }
 
 impl Credentials {
-    pub fn new(user: Box<str>, password: Box<str>) -> Self {
-        Self { user, password }
+    pub fn new(user: Box<str>, password: SecretString) -> Self {
+        Self {
+            user,
+            password,
+            key_file: None,
+        }
+    }
+
+    /// Add the content of a key file required on top of the password
+    pub fn with_key_file(mut self, key_file: &[u8]) -> Self {
+        self.key_file = Some(KeyFileHash::new(key_file));
+        return self;
+    }
+
+    /// Same as [`Credentials::with_key_file`], with an already hashed key file
+    pub fn with_key_file_hash(mut self, key_file: KeyFileHash) -> Self {
+        self.key_file = Some(key_file);
+        return self;
+    }
+
+    pub fn key_file(&self) -> Option<&KeyFileHash> {
+        self.key_file.as_ref()
     }
 
     pub fn user(&self) -> &str {
         self.user.as_ref()
     }
 
+    pub fn password(&self) -> &str {
+        self.password.expose()
+    }
+
+    /// Derive the key of a pre-key-slot vault from these credentials with
+    /// the vault's Argon2id parameters.
+    pub fn derive_key(&self, kdf: &KdfParams) -> Result<CipherKey, KdfError> {
+        return kdf.derive_key(self.password.as_bytes(), self.user.as_bytes());
+    }
+
+    /// Unsalted SHA-256 key used by vaults created before key derivation
+    /// parameters were stored in the `Metadata` table.
+    pub fn legacy_key(&self) -> CipherKey {
+        let mut key_hasher = hmac_sha256::Hash::new();
+        key_hasher.update(self.user.as_bytes());
+        key_hasher.update(self.password.as_bytes());
+        let mut key_bytes = key_hasher.finalize();
+        let key = CipherKey::new(key_bytes);
+        key_bytes.zeroize();
+        return key;
+    }
+
     // pub fn hash(&self) -> [u8; 32] {
     //     let mut key_hasher = hmac_sha256::Hash::new();
     //     key_hasher.update(self.user.as_bytes());
@@ -26,16 +78,75 @@ impl Credentials {
     // }
 }
 
-impl From<&Credentials> for CipherKey {
-    fn from(credentials: &Credentials) -> Self {
-        let mut key_hasher = hmac_sha256::Hash::new();
-        key_hasher.update(credentials.user.as_bytes());
-        key_hasher.update(credentials.password.as_bytes());
-        let key_bytes = key_hasher.finalize();
-        return CipherKey::new(key_bytes);
+/// What kind of secret unlocks a key slot
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeySlotKind {
+    Password,
+    KeyFile,
+    /// Recovery key, for when the master password is lost
+    Recovery,
+    /// Secret split into recovery shares
+    RecoveryShares,
+}
+
+impl KeySlotKind {
+    pub fn from_i64(value: i64) -> Option<Self> {
+        match value {
+            0 => Some(Self::Password),
+            1 => Some(Self::KeyFile),
+            2 => Some(Self::Recovery),
+            3 => Some(Self::RecoveryShares),
+            _ => None,
+        }
+    }
+
+    pub fn to_i64(self) -> i64 {
+        match self {
+            Self::Password => 0,
+            Self::KeyFile => 1,
+            Self::Recovery => 2,
+            Self::RecoveryShares => 3,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Password => "password",
+            Self::KeyFile => "key file",
+            Self::Recovery => "recovery key",
+            Self::RecoveryShares => "recovery shares",
+        }
+    }
+}
+
+/// A copy of the vault data key, wrapped with a key derived from one
+/// unlocking secret.
+#[derive(Debug)]
+pub struct KeySlot {
+    id: i64,
+    kind: KeySlotKind,
+    label: Box<str>,
+}
+
+impl KeySlot {
+    pub fn new(id: i64, kind: KeySlotKind, label: Box<str>) -> Self {
+        Self { id, kind, label }
+    }
+
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn kind(&self) -> KeySlotKind {
+        self.kind
+    }
+
+    pub fn label(&self) -> &str {
+        self.label.as_ref()
     }
 }
 
+#[derive(Debug)]
 pub struct EntryGroup {
     id: i64,
     data: EntryGroupData,
@@ -55,7 +166,7 @@ impl EntryGroup {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct EntryGroupData {
     group_name: Box<str>,
 }
@@ -68,6 +179,10 @@ impl EntryGroupData {
     pub fn name(&self) -> &str {
         self.group_name.as_ref()
     }
+
+    pub fn set_name(&mut self, group_name: Box<str>) {
+        self.group_name = group_name;
+    }
 }
 
 #[derive(Debug)]
@@ -90,19 +205,81 @@ impl Entry {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// File attached to an entry, without its content which is streamed by
+/// [`database::Database::read_attachment`]
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    id: i64,
+    name: Box<str>,
+    size: u64,
+}
+
+impl Attachment {
+    pub fn new(id: i64, name: Box<str>, size: u64) -> Self {
+        Self { id, name, size }
+    }
+
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_ref()
+    }
+
+    /// Size of the content in bytes
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+/// Fields added after the first release default to empty, so that older
+/// entries still deserialize. The timestamps are set by the
+/// [`database::Database`] when the entry is written, they are `None` for
+/// entries written before they were tracked.
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct EntryData {
     entry_name: Box<str>,
     username: Box<str>,
-    password: Box<str>,
+    password: SecretString,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    urls: Vec<Box<str>>,
+    #[serde(default, skip_serializing_if = "str::is_empty")]
+    notes: SecretString,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    custom_fields: Vec<CustomField>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    otp: Option<Otp>,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    tags: BTreeSet<Box<str>>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    favorite: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    modified: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    password_changed: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    accessed: Option<u64>,
 }
 
 impl EntryData {
-    pub fn new(entry_name: Box<str>, username: Box<str>, password: Box<str>) -> Self {
+    pub fn new(entry_name: Box<str>, username: Box<str>, password: SecretString) -> Self {
         Self {
             entry_name,
             username,
             password,
+            urls: Vec::new(),
+            notes: SecretString::default(),
+            custom_fields: Vec::new(),
+            otp: None,
+            tags: BTreeSet::new(),
+            favorite: false,
+            created: None,
+            modified: None,
+            password_changed: None,
+            accessed: None,
         }
     }
 
@@ -115,6 +292,214 @@ impl EntryData {
     }
 
     pub fn password(&self) -> &str {
-        self.password.as_ref()
+        self.password.expose()
+    }
+
+    pub fn set_name(&mut self, entry_name: Box<str>) {
+        self.entry_name = entry_name;
+    }
+
+    pub fn set_username(&mut self, username: Box<str>) {
+        self.username = username;
+    }
+
+    pub fn set_password(&mut self, password: SecretString) {
+        self.password = password;
+    }
+
+    pub fn urls(&self) -> &[Box<str>] {
+        self.urls.as_slice()
+    }
+
+    /// Add a URL, unless the entry already has it
+    pub fn add_url(&mut self, url: Box<str>) {
+        if !self.urls.contains(&url) {
+            self.urls.push(url);
+        }
+    }
+
+    /// Remove a URL, returning whether the entry had it
+    pub fn remove_url(&mut self, url: &str) -> bool {
+        let len = self.urls.len();
+        self.urls.retain(|other| other.as_ref() != url);
+        return self.urls.len() != len;
+    }
+
+    pub fn notes(&self) -> &str {
+        self.notes.expose()
+    }
+
+    pub fn set_notes(&mut self, notes: SecretString) {
+        self.notes = notes;
+    }
+
+    pub fn custom_fields(&self) -> &[CustomField] {
+        self.custom_fields.as_slice()
+    }
+
+    pub fn custom_field(&self, name: &str) -> Option<&CustomField> {
+        return self.custom_fields.iter().find(|field| field.name() == name);
+    }
+
+    /// Add a custom field, replacing the one with the same name if any
+    pub fn set_custom_field(&mut self, field: CustomField) {
+        match self
+            .custom_fields
+            .iter_mut()
+            .find(|other| other.name == field.name)
+        {
+            Some(other) => *other = field,
+            None => self.custom_fields.push(field),
+        }
+    }
+
+    /// Remove a custom field, returning whether the entry had it
+    pub fn remove_custom_field(&mut self, name: &str) -> bool {
+        let len = self.custom_fields.len();
+        self.custom_fields.retain(|field| field.name() != name);
+        return self.custom_fields.len() != len;
+    }
+
+    pub fn otp(&self) -> Option<&Otp> {
+        self.otp.as_ref()
+    }
+
+    pub fn otp_mut(&mut self) -> Option<&mut Otp> {
+        self.otp.as_mut()
+    }
+
+    pub fn set_otp(&mut self, otp: Option<Otp>) {
+        self.otp = otp;
+    }
+
+    /// Tags of the entry, sorted
+    pub fn tags(&self) -> impl Iterator<Item = &str> {
+        self.tags.iter().map(|tag| tag.as_ref())
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.contains(tag)
+    }
+
+    /// Add a tag, returning whether the entry did not have it yet
+    pub fn add_tag(&mut self, tag: Box<str>) -> bool {
+        self.tags.insert(tag)
+    }
+
+    /// Remove a tag, returning whether the entry had it
+    pub fn remove_tag(&mut self, tag: &str) -> bool {
+        self.tags.remove(tag)
+    }
+
+    pub fn is_favorite(&self) -> bool {
+        self.favorite
+    }
+
+    pub fn set_favorite(&mut self, favorite: bool) {
+        self.favorite = favorite;
+    }
+
+    /// When the entry was added, see [`crate::timestamp`]
+    pub fn created(&self) -> Option<u64> {
+        self.created
+    }
+
+    /// When any field of the entry last changed
+    pub fn modified(&self) -> Option<u64> {
+        self.modified
+    }
+
+    pub fn password_changed(&self) -> Option<u64> {
+        self.password_changed
+    }
+
+    /// When a secret of the entry was last read, e.g. its password or an OTP
+    /// code
+    pub fn accessed(&self) -> Option<u64> {
+        self.accessed
+    }
+}
+
+/// Named value of an entry, e.g. an API key, a PIN or a security answer.
+/// Concealed values are not echoed when typed nor shown in prompts.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CustomField {
+    name: Box<str>,
+    value: SecretString,
+    #[serde(default)]
+    concealed: bool,
+}
+
+impl CustomField {
+    pub fn new(name: Box<str>, value: SecretString, concealed: bool) -> Self {
+        Self {
+            name,
+            value,
+            concealed,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_ref()
+    }
+
+    pub fn value(&self) -> &str {
+        self.value.expose()
+    }
+
+    pub fn is_concealed(&self) -> bool {
+        self.concealed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde::Serialize;
+
+    use super::{CustomField, EntryData};
+
+    #[test]
+    fn deserialize_entry_without_new_fields() {
+        #[derive(Serialize)]
+        struct FirstEntryData<'a> {
+            entry_name: &'a str,
+            username: &'a str,
+            password: &'a str,
+        }
+        let mut serialized = Vec::new();
+        let first = FirstEntryData {
+            entry_name: "name",
+            username: "username",
+            password: "password",
+        };
+        ciborium::into_writer(&first, &mut serialized).unwrap();
+
+        let mut entry_data: EntryData = ciborium::from_reader(serialized.as_slice()).unwrap();
+        assert_eq!(entry_data.password(), "password");
+        assert!(entry_data.urls().is_empty());
+        assert_eq!(entry_data.notes(), "");
+        assert!(entry_data.custom_fields().is_empty());
+        assert!(entry_data.otp().is_none());
+        assert_eq!(entry_data.tags().count(), 0);
+        assert!(!entry_data.is_favorite());
+        assert_eq!(entry_data.modified(), None);
+
+        entry_data.add_url("https://example.com".into());
+        entry_data.set_notes("notes".into());
+        entry_data.set_custom_field(CustomField::new("pin".into(), "1234".into(), true));
+        entry_data.set_custom_field(CustomField::new("pin".into(), "4321".into(), true));
+        entry_data.add_tag("shared".into());
+        entry_data.add_tag("prod".into());
+        assert!(!entry_data.add_tag("prod".into()));
+        entry_data.set_favorite(true);
+        let mut serialized = Vec::new();
+        ciborium::into_writer(&entry_data, &mut serialized).unwrap();
+        let entry_data: EntryData = ciborium::from_reader(serialized.as_slice()).unwrap();
+        assert_eq!(entry_data.urls().len(), 1);
+        assert_eq!(entry_data.notes(), "notes");
+        assert_eq!(entry_data.custom_fields().len(), 1);
+        assert_eq!(entry_data.custom_field("pin").unwrap().value(), "4321");
+        assert_eq!(entry_data.tags().collect::<Vec<_>>(), ["prod", "shared"]);
+        assert!(entry_data.is_favorite());
     }
 }
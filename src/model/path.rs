@@ -0,0 +1,141 @@
+//! Slash-separated paths addressing groups and entries from the root group,
+//! e.g. `Work/AWS/prod-root`. A `/` or `\` inside a name is escaped with a
+//! backslash: the group `CI/CD` is written `CI\/CD`.
+
+use std::{convert::Infallible, fmt, str::FromStr};
+
+use crate::{Error, Result};
+
+pub const SEPARATOR: char = '/';
+const ESCAPE: char = '\\';
+
+/// A group or an entry given either by its id or by its path. A path made
+/// only of digits is read as an id, prefix it with `/` to force a path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ItemRef {
+    Id(i64),
+    Path(String),
+}
+
+impl From<String> for ItemRef {
+    fn from(s: String) -> Self {
+        if let Ok(id) = s.parse() {
+            return Self::Id(id);
+        }
+        return Self::Path(s);
+    }
+}
+
+impl FromStr for ItemRef {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        return Ok(Self::from(s.to_string()));
+    }
+}
+
+impl fmt::Display for ItemRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Id(id) => write!(f, "{}", id),
+            Self::Path(path) => write!(f, "{}", path),
+        }
+    }
+}
+
+/// Split a path into unescaped names. Leading and trailing separators are
+/// ignored, so that `""` and `"/"` both address the root group.
+pub fn split(path: &str) -> Result<Vec<String>> {
+    let mut names = vec![];
+    let mut name = String::new();
+    let mut chars = path.chars();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            ESCAPE => match chars.next() {
+                Some(escaped @ (SEPARATOR | ESCAPE)) => name.push(escaped),
+                _ => {
+                    return Err(Error::InvalidInput(format!(
+                        "invalid escape sequence in path \"{}\"",
+                        path
+                    )))
+                }
+            },
+            SEPARATOR => names.push(std::mem::take(&mut name)),
+            ch => name.push(ch),
+        }
+    }
+    names.push(name);
+
+    if names.first().is_some_and(String::is_empty) {
+        names.remove(0);
+    }
+    if names.last().is_some_and(String::is_empty) {
+        names.pop();
+    }
+    if names.iter().any(String::is_empty) {
+        return Err(Error::InvalidInput(format!(
+            "empty name in path \"{}\"",
+            path
+        )));
+    }
+
+    return Ok(names);
+}
+
+/// Escape a single name so that it can be used as a path component
+pub fn escape(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for ch in name.chars() {
+        if ch == SEPARATOR || ch == ESCAPE {
+            escaped.push(ESCAPE);
+        }
+        escaped.push(ch);
+    }
+    return escaped;
+}
+
+/// Build a path from unescaped names
+pub fn join<'a>(names: impl IntoIterator<Item = &'a str>) -> String {
+    return names
+        .into_iter()
+        .map(escape)
+        .collect::<Vec<_>>()
+        .join(&SEPARATOR.to_string());
+}
+
+#[cfg(test)]
+mod test {
+    use super::{join, split, ItemRef};
+
+    #[test]
+    fn split_paths() {
+        assert_eq!(split("").unwrap(), Vec::<String>::new());
+        assert_eq!(split("/").unwrap(), Vec::<String>::new());
+        assert_eq!(
+            split("/Work/AWS/prod-root/").unwrap(),
+            vec!["Work", "AWS", "prod-root"]
+        );
+        assert_eq!(
+            split(r"Work/CI\/CD/a\\b").unwrap(),
+            vec!["Work", "CI/CD", r"a\b"]
+        );
+        assert!(split("Work//AWS").is_err());
+        assert!(split(r"Work\n").is_err());
+    }
+
+    #[test]
+    fn join_then_split() {
+        let names = ["Work", "CI/CD", r"back\slash"];
+        let path = join(names);
+        assert_eq!(path, r"Work/CI\/CD/back\\slash");
+        assert_eq!(split(&path).unwrap(), names);
+    }
+
+    #[test]
+    fn parse_item_ref() {
+        assert_eq!("42".parse(), Ok(ItemRef::Id(42)));
+        assert_eq!("/42".parse(), Ok(ItemRef::Path("/42".into())));
+        assert_eq!("Work/AWS".parse(), Ok(ItemRef::Path("Work/AWS".into())));
+    }
+}
@@ -0,0 +1,298 @@
+//! One-time passwords of entries, HOTP (RFC 4226) and TOTP (RFC 6238), set
+//! from the `otpauth://` URIs shown as QR codes by services.
+
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::{base32, secret::SecretString, Error, Result};
+
+const URI_SCHEME: &str = "otpauth://";
+/// Characters percent-encoded in URIs, the unreserved ones of RFC 3986 kept
+/// as well as `:` and `@`, common in labels such as `Example:alice@example.com`
+const URI_COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b':')
+    .remove(b'@')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OtpAlgorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl OtpAlgorithm {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Sha1 => "SHA1",
+            Self::Sha256 => "SHA256",
+            Self::Sha512 => "SHA512",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        return [Self::Sha1, Self::Sha256, Self::Sha512]
+            .into_iter()
+            .find(|algorithm| algorithm.name().eq_ignore_ascii_case(name));
+    }
+
+    fn mac(self, key: &[u8], message: &[u8]) -> Zeroizing<Vec<u8>> {
+        let mac = match self {
+            Self::Sha1 => hmac_sha1_compact::HMAC::mac(message, key).to_vec(),
+            Self::Sha256 => hmac_sha256::HMAC::mac(message, key).to_vec(),
+            Self::Sha512 => hmac_sha512::HMAC::mac(message, key).to_vec(),
+        };
+        return Zeroizing::new(mac);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OtpKind {
+    /// Time-based, a new code every `period` seconds
+    Totp { period: u64 },
+    /// Counter-based, the counter being incremented for each code
+    Hotp { counter: u64 },
+}
+
+/// OTP secret and parameters of an entry
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Otp {
+    kind: OtpKind,
+    /// Account name, often prefixed with the issuer, e.g. `Example:alice`
+    label: Box<str>,
+    issuer: Option<Box<str>>,
+    /// Uppercase base32 without padding
+    secret: SecretString,
+    algorithm: OtpAlgorithm,
+    digits: u32,
+}
+
+impl Otp {
+    pub const DEFAULT_DIGITS: u32 = 6;
+    pub const DEFAULT_PERIOD: u64 = 30;
+
+    /// Parse an `otpauth://totp/...` or `otpauth://hotp/...` URI. Missing
+    /// parameters default to SHA1, 6 digits and a 30 seconds period, the
+    /// counter being required for HOTP.
+    pub fn parse_uri(uri: &str) -> Result<Self> {
+        let invalid =
+            |reason: &str| Error::InvalidInput(format!("invalid otpauth URI: {}", reason));
+
+        let rest = uri
+            .trim()
+            .strip_prefix(URI_SCHEME)
+            .ok_or_else(|| invalid("expected otpauth://"))?;
+        let (kind, rest) = rest
+            .split_once('/')
+            .ok_or_else(|| invalid("missing label"))?;
+        let (label, query) = rest.split_once('?').unwrap_or((rest, ""));
+
+        let mut secret = None;
+        let mut issuer = None;
+        let mut algorithm = OtpAlgorithm::Sha1;
+        let mut digits = Self::DEFAULT_DIGITS;
+        let mut period = Self::DEFAULT_PERIOD;
+        let mut counter = None;
+        for parameter in query.split('&').filter(|parameter| !parameter.is_empty()) {
+            let (name, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+            let value = Zeroizing::new(decode_component(value)?);
+            match name.to_ascii_lowercase().as_str() {
+                "secret" => secret = Some(normalize_secret(&value)?),
+                "issuer" => issuer = Some(value.as_str().into()),
+                "algorithm" => {
+                    algorithm = OtpAlgorithm::from_name(&value)
+                        .ok_or_else(|| invalid("unknown algorithm"))?
+                }
+                "digits" => {
+                    digits = value
+                        .parse()
+                        .ok()
+                        .filter(|digits| (6..=10).contains(digits))
+                        .ok_or_else(|| invalid("digits must be between 6 and 10"))?
+                }
+                "period" => {
+                    period = value
+                        .parse()
+                        .ok()
+                        .filter(|period| *period > 0)
+                        .ok_or_else(|| invalid("invalid period"))?
+                }
+                "counter" => counter = Some(value.parse().map_err(|_| invalid("invalid counter"))?),
+                // e.g. an image URL
+                _ => {}
+            }
+        }
+
+        let kind = match kind.to_ascii_lowercase().as_str() {
+            "totp" => OtpKind::Totp { period },
+            "hotp" => OtpKind::Hotp {
+                counter: counter.ok_or_else(|| invalid("missing HOTP counter"))?,
+            },
+            _ => return Err(invalid("expected totp or hotp")),
+        };
+
+        return Ok(Self {
+            kind,
+            label: decode_component(label)?.into(),
+            issuer,
+            secret: secret.ok_or_else(|| invalid("missing secret"))?,
+            algorithm,
+            digits,
+        });
+    }
+
+    /// URI with the current parameters, e.g. the incremented HOTP counter
+    pub fn to_uri(&self) -> SecretString {
+        let (kind, parameter) = match self.kind {
+            OtpKind::Totp { period } => ("totp", format!("period={}", period)),
+            OtpKind::Hotp { counter } => ("hotp", format!("counter={}", counter)),
+        };
+        let mut uri = format!(
+            "{}{}/{}?secret={}&algorithm={}&digits={}&{}",
+            URI_SCHEME,
+            kind,
+            utf8_percent_encode(&self.label, URI_COMPONENT),
+            self.secret.expose(),
+            self.algorithm.name(),
+            self.digits,
+            parameter
+        );
+        if let Some(issuer) = &self.issuer {
+            uri.push_str("&issuer=");
+            uri.extend(utf8_percent_encode(issuer, URI_COMPONENT));
+        }
+        return SecretString::new(uri);
+    }
+
+    pub fn kind(&self) -> OtpKind {
+        self.kind
+    }
+
+    pub fn label(&self) -> &str {
+        self.label.as_ref()
+    }
+
+    /// HOTP code for `counter`, which TOTP derives from the time
+    pub fn code(&self, counter: u64) -> SecretString {
+        let key = Zeroizing::new(base32::decode(&self.secret).unwrap_or_default());
+        let mac = self.algorithm.mac(&key, &counter.to_be_bytes());
+
+        // dynamic truncation
+        let offset = (mac[mac.len() - 1] & 0x0f) as usize;
+        let mut truncated = [0u8; 4];
+        truncated.copy_from_slice(&mac[offset..offset + 4]);
+        let truncated = u32::from_be_bytes(truncated) & 0x7fff_ffff;
+
+        let code = truncated as u64 % 10u64.pow(self.digits);
+        return SecretString::new(format!("{:0width$}", code, width = self.digits as usize));
+    }
+
+    /// TOTP code at `unix_time`, and the seconds it remains valid
+    pub fn totp_code(&self, unix_time: u64) -> Result<(SecretString, u64)> {
+        let OtpKind::Totp { period } = self.kind else {
+            return Err(Error::InvalidInput("not a TOTP secret".into()));
+        };
+        let code = self.code(unix_time / period);
+        return Ok((code, period - unix_time % period));
+    }
+
+    /// HOTP code for the current counter, which is then incremented
+    pub fn next_hotp_code(&mut self) -> Result<SecretString> {
+        let OtpKind::Hotp { counter } = &mut self.kind else {
+            return Err(Error::InvalidInput("not a HOTP secret".into()));
+        };
+        let code_counter = *counter;
+        *counter += 1;
+        return Ok(self.code(code_counter));
+    }
+}
+
+fn decode_component(component: &str) -> Result<String> {
+    let decoded = percent_decode_str(component)
+        .decode_utf8()
+        .map_err(|_| Error::InvalidInput("invalid otpauth URI: not UTF-8".into()))?;
+    return Ok(decoded.into_owned());
+}
+
+/// Uppercase base32 without padding nor spaces, which must decode
+fn normalize_secret(secret: &str) -> Result<SecretString> {
+    let normalized: String = secret
+        .chars()
+        .filter(|ch| !ch.is_whitespace() && *ch != '=')
+        .map(|ch| ch.to_ascii_uppercase())
+        .collect();
+    let normalized = SecretString::new(normalized);
+    match base32::decode(&normalized) {
+        Some(key) if !key.is_empty() => {
+            drop(Zeroizing::new(key));
+            return Ok(normalized);
+        }
+        _ => {
+            return Err(Error::InvalidInput(
+                "invalid otpauth URI: the secret is not base32".into(),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Otp, OtpKind};
+    use crate::base32;
+
+    fn rfc_otp(kind: &str, key: &[u8], algorithm: &str, digits: u32) -> Otp {
+        let uri = format!(
+            "otpauth://{}/Example:alice?secret={}&algorithm={}&digits={}&counter=0",
+            kind,
+            base32::encode(key),
+            algorithm,
+            digits
+        );
+        return Otp::parse_uri(&uri).unwrap();
+    }
+
+    #[test]
+    fn rfc_4226_hotp() {
+        let mut otp = rfc_otp("hotp", b"12345678901234567890", "SHA1", 6);
+        let codes = ["755224", "287082", "359152", "969429", "338314", "254676"];
+        for code in codes {
+            assert_eq!(otp.next_hotp_code().unwrap().expose(), code);
+        }
+        assert_eq!(otp.kind(), OtpKind::Hotp { counter: 6 });
+        otp.totp_code(0).unwrap_err();
+    }
+
+    #[test]
+    fn rfc_6238_totp() {
+        let sha1 = rfc_otp("totp", b"12345678901234567890", "SHA1", 8);
+        let sha256 = rfc_otp("totp", b"12345678901234567890123456789012", "sha256", 8);
+        let sha512_key = b"1234567890123456789012345678901234567890123456789012345678901234";
+        let sha512 = rfc_otp("totp", sha512_key, "SHA512", 8);
+
+        let (code, remaining) = sha1.totp_code(59).unwrap();
+        assert_eq!((code.expose(), remaining), ("94287082", 1));
+        assert_eq!(sha256.totp_code(59).unwrap().0.expose(), "46119246");
+        assert_eq!(sha512.totp_code(59).unwrap().0.expose(), "90693936");
+        assert_eq!(sha1.totp_code(1111111109).unwrap().0.expose(), "07081804");
+        assert_eq!(sha256.totp_code(1111111109).unwrap().0.expose(), "68084774");
+        assert_eq!(sha512.totp_code(1111111109).unwrap().0.expose(), "25091201");
+    }
+
+    #[test]
+    fn uri_roundtrip() {
+        let uri = "otpauth://totp/ACME%20Co:john%40example.com?secret=jbsw y3dp ehpk 3pxp&issuer=ACME%20Co&period=60";
+        let otp = Otp::parse_uri(uri).unwrap();
+        assert_eq!(otp.label(), "ACME Co:john@example.com");
+        assert_eq!(otp.kind(), OtpKind::Totp { period: 60 });
+        assert_eq!(Otp::parse_uri(&otp.to_uri()).unwrap(), otp);
+
+        Otp::parse_uri("otpauth://hotp/label?secret=JBSWY3DPEHPK3PXP").unwrap_err();
+        Otp::parse_uri("otpauth://totp/label?secret=not-base32").unwrap_err();
+        Otp::parse_uri("otpauth://totp/label?digits=6").unwrap_err();
+        Otp::parse_uri("https://example.com").unwrap_err();
+    }
+}
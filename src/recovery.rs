@@ -0,0 +1,379 @@
+//! Recovery keys, unlocking a vault through their own key slot when the
+//! master password is lost, and the emergency kit they are printed in.
+//! Recovery shares split such a secret between several people.
+
+use std::{
+    fmt::{self, Write},
+    path::Path,
+};
+
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use zeroize::Zeroizing;
+
+use crate::{
+    base32,
+    encryption::CipherKey,
+    secret::SecretString,
+    shamir::{self, Share},
+    Error, Result,
+};
+
+const CHECKSUM_SIZE: usize = 5;
+/// Characters per group of the printed key
+const GROUP_SIZE: usize = 5;
+const GROUP_SEPARATOR: char = '-';
+const SHARE_CHECKSUM_SIZE: usize = 4;
+/// Characters per group of a printed share
+const SHARE_GROUP_SIZE: usize = 8;
+
+/// 160 random bits, printed as 8 groups of 5 base32 characters which end
+/// with a 40-bit checksum, e.g. `ABCDE-FGHIJ-...`. Typos are caught by the
+/// checksum before any key derivation.
+pub struct RecoveryKey(Zeroizing<[u8; RecoveryKey::SIZE]>);
+
+impl RecoveryKey {
+    pub const SIZE: usize = 20;
+
+    /// Generate a key with the OS random number generator.
+    pub fn generate() -> Self {
+        let mut key = Zeroizing::new([0u8; Self::SIZE]);
+        OsRng.fill_bytes(key.as_mut_slice());
+        return Self(key);
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_slice()
+    }
+
+    /// Key and checksum in base32, grouped for reading and typing
+    pub fn to_printable(&self) -> SecretString {
+        let mut bytes = Zeroizing::new(self.0.to_vec());
+        bytes.extend_from_slice(&checksum(self.as_bytes())[..CHECKSUM_SIZE]);
+        return encode_printable(&bytes, GROUP_SIZE);
+    }
+
+    /// Parse a printed key. Case, spaces, separators and the digits 0, 1 and
+    /// 8 typed for the letters O, I and B are tolerated.
+    pub fn parse(printable: &str) -> Result<Self> {
+        let invalid = || Error::InvalidInput("invalid recovery key, check for typos".into());
+
+        let bytes = decode_printable(printable).ok_or_else(invalid)?;
+        if bytes.len() != Self::SIZE + CHECKSUM_SIZE {
+            return Err(invalid());
+        }
+        let (key, key_checksum) = bytes.split_at(Self::SIZE);
+        if key_checksum != &checksum(key)[..CHECKSUM_SIZE] {
+            return Err(invalid());
+        }
+
+        let mut recovery_key = Zeroizing::new([0u8; Self::SIZE]);
+        recovery_key.copy_from_slice(key);
+        return Ok(Self(recovery_key));
+    }
+}
+
+impl fmt::Debug for RecoveryKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.write_str("RecoveryKey([redacted])");
+    }
+}
+
+/// One of the shares a recovery secret is split into. Each share records
+/// the threshold of shares needed and an identifier of its split, so that
+/// shares of different splits are not mixed up.
+pub struct RecoveryShare {
+    split_id: [u8; RecoveryShare::SPLIT_ID_SIZE],
+    threshold: u8,
+    share: Share,
+}
+
+impl RecoveryShare {
+    pub const SPLIT_ID_SIZE: usize = 2;
+
+    /// Number of shares needed to recover
+    pub fn threshold(&self) -> u8 {
+        self.threshold
+    }
+
+    /// Number of this share, from 1
+    pub fn number(&self) -> u8 {
+        self.share.x
+    }
+
+    /// Share and checksum in base32, grouped for reading and typing
+    pub fn to_printable(&self) -> SecretString {
+        let mut bytes = Zeroizing::new(vec![self.threshold, self.share.x]);
+        bytes.extend_from_slice(&self.split_id);
+        bytes.extend_from_slice(&self.share.y);
+        let share_checksum = checksum(&bytes);
+        bytes.extend_from_slice(&share_checksum[..SHARE_CHECKSUM_SIZE]);
+        return encode_printable(&bytes, SHARE_GROUP_SIZE);
+    }
+
+    /// Parse a printed share, as leniently as [`RecoveryKey::parse`]
+    pub fn parse(printable: &str) -> Result<Self> {
+        let invalid = || Error::InvalidInput("invalid recovery share, check for typos".into());
+
+        let bytes = decode_printable(printable).ok_or_else(invalid)?;
+        let header_size = 2 + Self::SPLIT_ID_SIZE;
+        if bytes.len() != header_size + CipherKey::SIZE + SHARE_CHECKSUM_SIZE {
+            return Err(invalid());
+        }
+        let (share, share_checksum) = bytes.split_at(bytes.len() - SHARE_CHECKSUM_SIZE);
+        if share_checksum != &checksum(share)[..SHARE_CHECKSUM_SIZE] {
+            return Err(invalid());
+        }
+
+        let mut split_id = [0u8; Self::SPLIT_ID_SIZE];
+        split_id.copy_from_slice(&share[2..header_size]);
+        return Ok(Self {
+            split_id,
+            threshold: share[0],
+            share: Share {
+                x: share[1],
+                y: Zeroizing::new(share[header_size..].to_vec()),
+            },
+        });
+    }
+}
+
+impl fmt::Debug for RecoveryShare {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(
+            f,
+            "RecoveryShare({} of {}, [redacted])",
+            self.share.x, self.threshold
+        );
+    }
+}
+
+/// Split `secret` into `count` shares, any `threshold` of which recover it
+/// with [`combine_shares`].
+pub fn split_secret(secret: &CipherKey, threshold: u8, count: u8) -> Result<Vec<RecoveryShare>> {
+    let mut split_id = [0u8; RecoveryShare::SPLIT_ID_SIZE];
+    OsRng.fill_bytes(&mut split_id);
+
+    let shares = shamir::split(secret.as_ref(), threshold, count)?
+        .into_iter()
+        .map(|share| RecoveryShare {
+            split_id,
+            threshold,
+            share,
+        })
+        .collect();
+    return Ok(shares);
+}
+
+/// Recover the secret from at least a threshold of shares of the same split
+pub fn combine_shares(shares: Vec<RecoveryShare>) -> Result<CipherKey> {
+    let Some(first) = shares.first() else {
+        return Err(Error::InvalidInput("no recovery shares".into()));
+    };
+    let (split_id, threshold) = (first.split_id, first.threshold);
+    if shares
+        .iter()
+        .any(|share| share.split_id != split_id || share.threshold != threshold)
+    {
+        return Err(Error::InvalidInput(
+            "the recovery shares come from different splits".into(),
+        ));
+    }
+    if shares.len() < threshold as usize {
+        return Err(Error::InvalidInput(format!(
+            "{} recovery shares are needed, got {}",
+            threshold,
+            shares.len()
+        )));
+    }
+
+    let shares: Vec<Share> = shares.into_iter().map(|share| share.share).collect();
+    let secret = shamir::combine(&shares)?;
+    let secret: [u8; CipherKey::SIZE] = secret
+        .as_slice()
+        .try_into()
+        .map_err(|_| Error::InvalidInput("invalid recovery share".into()))?;
+    return Ok(CipherKey::new(secret));
+}
+
+/// Printable page for an offline safe: where the vault is, how to recover
+/// it and the recovery key. HTML if `html`, plain text otherwise.
+pub fn emergency_kit(
+    vault_path: &Path,
+    user: &str,
+    requires_key_file: bool,
+    recovery_key: &RecoveryKey,
+    html: bool,
+) -> SecretString {
+    let vault_path = vault_path.display().to_string();
+    let mut lines = vec![
+        ("Vault".to_string(), vault_path.clone()),
+        ("User".to_string(), user.to_string()),
+    ];
+    if requires_key_file {
+        lines.push((
+            "Key file".to_string(),
+            "required on top of the new password, keep a copy with this kit".to_string(),
+        ));
+    }
+    let command = format!("password-cli --vault {} recover", shell_quote(&vault_path));
+    let key = recovery_key.to_printable();
+
+    let mut kit = String::new();
+    if html {
+        kit.push_str(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>password-cli emergency kit</title>\n</head>\n<body>\n\
+             <h1>password-cli emergency kit</h1>\n<dl>\n",
+        );
+        for (label, value) in &lines {
+            let _ = writeln!(
+                kit,
+                "<dt>{}</dt><dd>{}</dd>",
+                escape_html(label),
+                escape_html(value)
+            );
+        }
+        let _ = write!(
+            kit,
+            "</dl>\n<p>If the master password is lost, run:</p>\n<pre>{}</pre>\n\
+             <p>and type this recovery key, then choose a new master password:</p>\n\
+             <pre style=\"font-size: x-large\">{}</pre>\n</body>\n</html>\n",
+            escape_html(&command),
+            key.expose()
+        );
+    } else {
+        kit.push_str("password-cli emergency kit\n\n");
+        for (label, value) in &lines {
+            let _ = writeln!(kit, "{}: {}", label, value);
+        }
+        let _ = write!(
+            kit,
+            "\nIf the master password is lost, run:\n\n    {}\n\n\
+             and type this recovery key, then choose a new master password:\n\n    {}\n",
+            command,
+            key.expose()
+        );
+    }
+
+    return SecretString::new(kit);
+}
+
+/// Quote `text` as a single POSIX shell word
+fn shell_quote(text: &str) -> String {
+    return format!("'{}'", text.replace('\'', "'\\''"));
+}
+
+fn escape_html(text: &str) -> String {
+    return text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;");
+}
+
+/// SHA-256, truncated by the callers
+fn checksum(data: &[u8]) -> [u8; 32] {
+    return hmac_sha256::Hash::hash(data);
+}
+
+fn encode_printable(bytes: &[u8], group_size: usize) -> SecretString {
+    let encoded = Zeroizing::new(base32::encode(bytes));
+
+    let mut printable = String::with_capacity(encoded.len() * (group_size + 1) / group_size);
+    for (i, ch) in encoded.chars().enumerate() {
+        if i > 0 && i % group_size == 0 {
+            printable.push(GROUP_SEPARATOR);
+        }
+        printable.push(ch);
+    }
+    return SecretString::new(printable);
+}
+
+/// Decode base32 typed by a human, `None` if it has invalid characters
+fn decode_printable(printable: &str) -> Option<Zeroizing<Vec<u8>>> {
+    let normalized: Zeroizing<String> = Zeroizing::new(
+        printable
+            .chars()
+            .filter(|ch| !ch.is_whitespace() && *ch != GROUP_SEPARATOR)
+            .map(|ch| match ch.to_ascii_uppercase() {
+                '0' => 'O',
+                '1' => 'I',
+                '8' => 'B',
+                ch => ch,
+            })
+            .collect(),
+    );
+    return base32::decode(&normalized).map(Zeroizing::new);
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{combine_shares, emergency_kit, split_secret, RecoveryKey, RecoveryShare};
+    use crate::encryption::CipherKey;
+
+    #[test]
+    fn print_then_parse() {
+        let key = RecoveryKey::generate();
+        let printable = key.to_printable();
+        assert_eq!(printable.len(), 8 * 5 + 7);
+        assert_eq!(printable.split('-').count(), 8);
+
+        let parsed = RecoveryKey::parse(&printable).unwrap();
+        assert_eq!(parsed.as_bytes(), key.as_bytes());
+        let sloppy = printable.to_lowercase().replace('-', " ");
+        let parsed = RecoveryKey::parse(&sloppy).unwrap();
+        assert_eq!(parsed.as_bytes(), key.as_bytes());
+    }
+
+    #[test]
+    fn checksum_catches_typos() {
+        let printable = RecoveryKey::generate().to_printable();
+        let mut chars: Vec<char> = printable.chars().collect();
+        chars[0] = if chars[0] == 'A' { 'B' } else { 'A' };
+        let typo: String = chars.into_iter().collect();
+
+        RecoveryKey::parse(&typo).unwrap_err();
+        RecoveryKey::parse(&printable[..20]).unwrap_err();
+    }
+
+    #[test]
+    fn print_then_combine_shares() {
+        let secret = CipherKey::generate();
+        let shares = split_secret(&secret, 2, 3).unwrap();
+        let printed: Vec<_> = shares.iter().map(|share| share.to_printable()).collect();
+        assert_eq!(printed[0].split('-').count(), 8);
+
+        let parsed = vec![
+            RecoveryShare::parse(&printed[2]).unwrap(),
+            RecoveryShare::parse(&printed[0].to_lowercase()).unwrap(),
+        ];
+        assert_eq!(parsed[0].number(), 3);
+        assert_eq!(parsed[0].threshold(), 2);
+        let combined = combine_shares(parsed).unwrap();
+        assert_eq!(combined.as_ref(), secret.as_ref());
+
+        let too_few = vec![RecoveryShare::parse(&printed[1]).unwrap()];
+        combine_shares(too_few).unwrap_err();
+        let other_split = split_secret(&secret, 2, 3).unwrap();
+        let mixed = vec![
+            RecoveryShare::parse(&printed[0]).unwrap(),
+            other_split.into_iter().nth(1).unwrap(),
+        ];
+        combine_shares(mixed).unwrap_err();
+    }
+
+    #[test]
+    fn emergency_kit_quotes_the_vault_path() {
+        let key = RecoveryKey::generate();
+        let kit = emergency_kit(
+            Path::new("/home/o'brien/vault.sqlite"),
+            "bob",
+            false,
+            &key,
+            false,
+        );
+        assert!(kit.contains("password-cli --vault '/home/o'\\''brien/vault.sqlite' recover\n"));
+    }
+}
@@ -0,0 +1,83 @@
+//! Secrets held in memory: their buffers are zeroed when dropped, and they
+//! are redacted in `Debug` output so that they never end up in logs.
+
+use std::{fmt, ops::Deref};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// A password or passphrase. It is serialized as a plain string.
+#[derive(Clone, Default, PartialEq, Eq, Zeroize, ZeroizeOnDrop)]
+pub struct SecretString(String);
+
+impl SecretString {
+    pub fn new(secret: String) -> Self {
+        return Self(secret);
+    }
+
+    pub fn expose(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl Deref for SecretString {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        return self.expose();
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(secret: String) -> Self {
+        return Self::new(secret);
+    }
+}
+
+impl From<Box<str>> for SecretString {
+    fn from(secret: Box<str>) -> Self {
+        return Self::new(secret.into_string());
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(secret: &str) -> Self {
+        return Self::new(secret.to_string());
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.write_str("[redacted]");
+    }
+}
+
+impl Serialize for SecretString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        return serializer.serialize_str(self.expose());
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        return Ok(Self::new(String::deserialize(deserializer)?));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SecretString;
+    use crate::{encryption::CipherKey, model::EntryData};
+
+    #[test]
+    fn debug_is_redacted() {
+        let secret = SecretString::from("hunter2");
+        assert_eq!(format!("{:?}", secret), "[redacted]");
+        assert_eq!(secret.expose(), "hunter2");
+
+        let entry_data = EntryData::new("name".into(), "username".into(), secret);
+        assert!(!format!("{:?}", entry_data).contains("hunter2"));
+        let key = CipherKey::new([42; 32]);
+        assert!(!format!("{:?}", key).contains("42"));
+    }
+}
@@ -0,0 +1,159 @@
+//! Shamir secret sharing over GF(256): a secret is split byte by byte into
+//! shares, any `threshold` of which reconstruct it while fewer reveal
+//! nothing about it.
+
+use std::fmt;
+
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use zeroize::Zeroizing;
+
+use crate::{Error, Result};
+
+/// One share: the x coordinate, never 0, and the polynomial values at x for
+/// each byte of the secret
+pub struct Share {
+    pub x: u8,
+    pub y: Zeroizing<Vec<u8>>,
+}
+
+impl fmt::Debug for Share {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "Share({}, [redacted])", self.x);
+    }
+}
+
+/// Split `secret` into `count` shares, any `threshold` of which are needed to
+/// reconstruct it.
+pub fn split(secret: &[u8], threshold: u8, count: u8) -> Result<Vec<Share>> {
+    if threshold < 2 || count < threshold {
+        return Err(Error::InvalidInput(format!(
+            "invalid threshold {} for {} shares, it must be at least 2 and at most the number of shares",
+            threshold, count
+        )));
+    }
+
+    // one polynomial per secret byte, its constant term being the byte
+    let mut coefficients = Zeroizing::new(vec![0u8; secret.len() * threshold as usize]);
+    OsRng.fill_bytes(coefficients.as_mut_slice());
+    for (polynomial, &byte) in coefficients
+        .chunks_exact_mut(threshold as usize)
+        .zip(secret)
+    {
+        polynomial[0] = byte;
+    }
+
+    let shares = (1..=count)
+        .map(|x| {
+            let y = coefficients
+                .chunks_exact(threshold as usize)
+                .map(|polynomial| evaluate(polynomial, x))
+                .collect();
+            Share {
+                x,
+                y: Zeroizing::new(y),
+            }
+        })
+        .collect();
+    return Ok(shares);
+}
+
+/// Reconstruct the secret from shares of the same split. Given fewer shares
+/// than the threshold, the result is garbage: checking it is up to the
+/// caller.
+pub fn combine(shares: &[Share]) -> Result<Zeroizing<Vec<u8>>> {
+    let Some(first) = shares.first() else {
+        return Err(Error::InvalidInput("no shares to combine".into()));
+    };
+    for (i, share) in shares.iter().enumerate() {
+        if share.x == 0 || share.y.len() != first.y.len() {
+            return Err(Error::InvalidInput("invalid share".into()));
+        }
+        if shares[..i].iter().any(|other| other.x == share.x) {
+            return Err(Error::InvalidInput(format!(
+                "share {} given twice",
+                share.x
+            )));
+        }
+    }
+
+    // Lagrange interpolation at x = 0, subtraction being addition in GF(256)
+    let mut secret = Zeroizing::new(vec![0u8; first.y.len()]);
+    for share in shares {
+        let mut basis = 1u8;
+        for other in shares.iter().filter(|other| other.x != share.x) {
+            basis = mul(basis, mul(other.x, inverse(other.x ^ share.x)));
+        }
+        for (byte, &y) in secret.iter_mut().zip(share.y.iter()) {
+            *byte ^= mul(basis, y);
+        }
+    }
+    return Ok(secret);
+}
+
+/// Horner's method, coefficients by increasing degree
+fn evaluate(polynomial: &[u8], x: u8) -> u8 {
+    return polynomial
+        .iter()
+        .rev()
+        .fold(0, |value, &coefficient| mul(value, x) ^ coefficient);
+}
+
+/// Multiplication modulo the AES polynomial x^8 + x^4 + x^3 + x + 1, without
+/// data-dependent branches or table lookups
+fn mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0u8;
+    for _ in 0..8 {
+        product ^= a & 0u8.wrapping_sub(b & 1);
+        let carry = 0u8.wrapping_sub(a >> 7);
+        a = (a << 1) ^ (carry & 0x1b);
+        b >>= 1;
+    }
+    return product;
+}
+
+/// a^254, the multiplicative inverse of a non-zero `a`
+fn inverse(a: u8) -> u8 {
+    let mut result = 1u8;
+    let mut power = a;
+    let mut exponent = 254u8;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = mul(result, power);
+        }
+        power = mul(power, power);
+        exponent >>= 1;
+    }
+    return result;
+}
+
+#[cfg(test)]
+mod test {
+    use super::{combine, inverse, mul, split};
+
+    #[test]
+    fn field_inverse() {
+        for a in 1..=255u8 {
+            assert_eq!(mul(a, inverse(a)), 1);
+        }
+    }
+
+    #[test]
+    fn any_threshold_of_shares_combine() {
+        let secret = b"correct horse battery staple".to_vec();
+        let mut shares = split(&secret, 3, 5).unwrap();
+        assert_eq!(shares.len(), 5);
+
+        let combined = combine(&shares[1..4]).unwrap();
+        assert_eq!(combined.as_slice(), secret.as_slice());
+        let combined = combine(&shares).unwrap();
+        assert_eq!(combined.as_slice(), secret.as_slice());
+        shares.swap(0, 4);
+        let combined = combine(&shares[..3]).unwrap();
+        assert_eq!(combined.as_slice(), secret.as_slice());
+
+        let combined = combine(&shares[..2]).unwrap();
+        assert_ne!(combined.as_slice(), secret.as_slice());
+        split(&secret, 1, 5).unwrap_err();
+        split(&secret, 4, 3).unwrap_err();
+    }
+}
@@ -0,0 +1,161 @@
+//! Timestamps of entries, in seconds since the Unix epoch, shown as UTC
+//! RFC 3339 dates such as `2024-05-01T12:00:00Z`.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{Error, Result};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+pub fn now() -> u64 {
+    // a clock before 1970 is only possible when badly misconfigured
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+}
+
+/// UTC date and time, e.g. `2024-05-01T12:00:00Z`
+pub fn format(timestamp: u64) -> String {
+    let (year, month, day) = civil_from_days((timestamp / SECONDS_PER_DAY) as i64);
+    let seconds = timestamp % SECONDS_PER_DAY;
+    return format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    );
+}
+
+/// Parse a UTC date `YYYY-MM-DD` (at midnight), a date and time
+/// `YYYY-MM-DDTHH:MM:SSZ` as written by [`format`], or `<N>d`: N days before
+/// `now`.
+pub fn parse(value: &str, now: u64) -> Result<u64> {
+    let invalid = || {
+        Error::InvalidInput(format!(
+            "invalid date \"{}\", expected YYYY-MM-DD, YYYY-MM-DDTHH:MM:SSZ or a number of days such as 90d",
+            value
+        ))
+    };
+
+    if let Some(days) = value.strip_suffix('d') {
+        let days: u64 = days.parse().map_err(|_| invalid())?;
+        return Ok(now.saturating_sub(days.saturating_mul(SECONDS_PER_DAY)));
+    }
+
+    let (date, time) = match value.split_once('T') {
+        Some((date, time)) => (date, Some(time.strip_suffix('Z').ok_or_else(invalid)?)),
+        None => (value, None),
+    };
+    let numbers = |text: &str, separator: char, count: usize| -> Result<Vec<u32>> {
+        let numbers = text
+            .split(separator)
+            .map(|number| {
+                if number.is_empty() || !number.bytes().all(|byte| byte.is_ascii_digit()) {
+                    return Err(invalid());
+                }
+                number.parse::<u32>().map_err(|_| invalid())
+            })
+            .collect::<Result<Vec<u32>>>()?;
+        if numbers.len() != count {
+            return Err(invalid());
+        }
+        return Ok(numbers);
+    };
+
+    let date = numbers(date, '-', 3)?;
+    let (year, month, day) = (date[0] as i64, date[1], date[2]);
+    if year < 1970 || !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return Err(invalid());
+    }
+    let seconds = match time {
+        Some(time) => {
+            let time = numbers(time, ':', 3)?;
+            if time[0] > 23 || time[1] > 59 || time[2] > 59 {
+                return Err(invalid());
+            }
+            (time[0] * 3600 + time[1] * 60 + time[2]) as u64
+        }
+        None => 0,
+    };
+
+    return Ok(days_from_civil(year, month, day) as u64 * SECONDS_PER_DAY + seconds);
+}
+
+fn is_leap_year(year: i64) -> bool {
+    return year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of a date of the proleptic Gregorian calendar, see
+/// <https://howardhinnant.github.io/date_algorithms.html>
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month as i64 + 9) % 12) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    return era * 146097 + day_of_era - 719468;
+}
+
+/// Inverse of [`days_from_civil`]
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    return (year, month, day);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{format, parse};
+
+    #[test]
+    fn format_then_parse() {
+        assert_eq!(format(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(format(1_700_000_000), "2023-11-14T22:13:20Z");
+        for timestamp in [0, 951_782_400, 1_700_000_000, 4_102_444_799] {
+            assert_eq!(parse(&format(timestamp), 0).unwrap(), timestamp);
+        }
+
+        assert_eq!(parse("2000-02-29", 0).unwrap(), 951_782_400);
+        assert_eq!(parse("90d", 100 * 86_400).unwrap(), 10 * 86_400);
+        assert_eq!(parse("90d", 0).unwrap(), 0);
+        for invalid in [
+            "2001-02-29",
+            "2000-13-01",
+            "2000-1-1x",
+            "1969-12-31",
+            "2000-01-01T24:00:00Z",
+            "2000-01-01T12:00:00",
+            "+2000-01-01",
+            "d",
+            "",
+        ] {
+            parse(invalid, 0).unwrap_err();
+        }
+    }
+}